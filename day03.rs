@@ -7,7 +7,7 @@ fn common<T: std::cmp::PartialEq + Copy>(a: &[T], b: &[T]) -> Vec<T> {
 }
 
 fn first_common<T: std::cmp::PartialEq + Copy>(a: &[T], b: &[T]) -> Option<T> {
-    common(a, b).get(0).copied()
+    common(a, b).first().copied()
 }
 
 fn score(c: u8) -> u32 {
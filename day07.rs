@@ -126,8 +126,8 @@ fn main() {
     }
     println!(
         "Part 1: {}",
-        dirs.iter()
-            .map(|(k, _)| dir_size(&dirs, k))
+        dirs.keys()
+            .map(|k| dir_size(&dirs, k))
             .filter(|sz| *sz <= 100_000)
             .sum::<usize>()
     );
@@ -138,8 +138,8 @@ fn main() {
     let must_free_at_least = space_used - (total_space - space_needed);
     println!(
         "Part 2: {}",
-        dirs.iter()
-            .map(|(k, _)| dir_size(&dirs, k))
+        dirs.keys()
+            .map(|k| dir_size(&dirs, k))
             .filter(|sz| *sz >= must_free_at_least)
             .min()
             .unwrap()
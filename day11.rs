@@ -98,7 +98,7 @@ impl Monkey {
     }
 }
 
-fn full_round(monkeys: &mut Vec<Monkey>, worry_divisor: usize, supermod: usize) {
+fn full_round(monkeys: &mut [Monkey], worry_divisor: usize, supermod: usize) {
     for i in 0..monkeys.len() {
         let throws = monkeys[i].do_round(worry_divisor, supermod);
         for (target, item) in throws {
@@ -107,7 +107,7 @@ fn full_round(monkeys: &mut Vec<Monkey>, worry_divisor: usize, supermod: usize)
     }
 }
 
-fn monkey_business(monkeys: &Vec<Monkey>) -> usize {
+fn monkey_business(monkeys: &[Monkey]) -> usize {
     assert!(monkeys.len() >= 2);
     let mut activities: Vec<_> = monkeys.iter().map(|m| m.activity).collect();
     activities.sort();
@@ -1,57 +1,302 @@
-use std::cmp::Ordering;
-use std::io;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt::{self, Display, Formatter, Write};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write as _};
+use std::path::PathBuf;
+use std::slice;
 use std::str::FromStr;
+use std::{env, fs, io};
 
-use anyhow::{Error, Ok, Result};
-use json::JsonValue;
+use anyhow::{anyhow, Error, Result};
+use itertools::Itertools;
 
-#[derive(Debug, Eq)]
+// A non-negative integer of any size, stored as its decimal digits without
+// leading zeros (except for zero itself, which is "0")
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Num(String);
+
+impl Num {
+    fn from_digits(digits: &str) -> Self {
+        assert!(!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()));
+        match digits.trim_start_matches('0') {
+            "" => Self("0".to_owned()),
+            trimmed => Self(trimmed.to_owned()),
+        }
+    }
+}
+
+impl Display for Num {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Ord for Num {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Without leading zeros, a longer number is always bigger
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for Num {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Clone, Debug, Eq)]
 enum Packet {
-    Int(u64),
+    Int(Num),
     List(Vec<Packet>),
 }
 
 use Packet::*;
 
+// Packets are compared as described in the puzzle, with an integer treated
+// as a one-item list when compared with a list. This is a total preorder, and
+// the equality it implies is the equivalence used by Eq: packets are equal
+// when neither is ordered before the other, so e.g. 1, [1] and [[1]] are all
+// equal (but [] is less than [[]]). Packet doesn't implement Hash, which
+// would have to agree with this. Ord, PartialOrd and PartialEq all go through
+// Packet::compare(), so they can't disagree.
 impl Ord for Packet {
     fn cmp(&self, other: &Self) -> Ordering {
-        match (self, other) {
-            (Int(a), Int(b)) => a.cmp(b),
-            (Int(a), b) => List(vec![Int(*a)]).cmp(b),
-            (a, Int(b)) => a.cmp(&List(vec![Int(*b)])),
-            (List(a), List(b)) => a.cmp(b),
-        }
+        self.compare(other, 0, None)
     }
 }
 
 impl PartialOrd for Packet {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self, other) {
-            (Int(a), Int(b)) => a.partial_cmp(b),
-            (Int(a), b) => List(vec![Int(*a)]).partial_cmp(b),
-            (a, Int(b)) => a.partial_cmp(&List(vec![Int(*b)])),
-            (List(a), List(b)) => a.partial_cmp(b),
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl PartialEq for Packet {
     fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+// Add a line at the given depth to an explanation, if there is one
+fn note(out: &mut Option<&mut String>, depth: usize, text: fmt::Arguments) {
+    if let Some(out) = out.as_deref_mut() {
+        writeln!(out, "{:1$}- {text}", "", 2 * depth).unwrap();
+    }
+}
+
+impl Packet {
+    // The comparison behind cmp() and eq(). If 'out' is given, each step is
+    // explained there, in the style of the puzzle text.
+    fn compare(&self, other: &Self, depth: usize, mut out: Option<&mut String>) -> Ordering {
+        note(&mut out, depth, format_args!("Compare {self} vs {other}"));
         match (self, other) {
-            (Int(a), Int(b)) => a.eq(b),
-            (Int(a), b) => List(vec![Int(*a)]).eq(b),
-            (a, Int(b)) => a.eq(&List(vec![Int(*b)])),
-            (List(a), List(b)) => a.eq(b),
+            (Int(a), Int(b)) => {
+                let ret = a.cmp(b);
+                let verdict = match ret {
+                    Ordering::Less => "Left side is smaller, so inputs are in the right order",
+                    Ordering::Greater => {
+                        "Right side is smaller, so inputs are not in the right order"
+                    }
+                    Ordering::Equal => return ret,
+                };
+                note(&mut out, depth + 1, format_args!("{verdict}"));
+                ret
+            }
+            (Int(_), List(b)) => {
+                note(
+                    &mut out,
+                    depth + 1,
+                    format_args!("Mixed types; convert left to [{self}] and retry comparison"),
+                );
+                note(
+                    &mut out,
+                    depth + 1,
+                    format_args!("Compare [{self}] vs {other}"),
+                );
+                Self::compare_lists(slice::from_ref(self), b, depth + 1, out)
+            }
+            (List(a), Int(_)) => {
+                note(
+                    &mut out,
+                    depth + 1,
+                    format_args!("Mixed types; convert right to [{other}] and retry comparison"),
+                );
+                note(
+                    &mut out,
+                    depth + 1,
+                    format_args!("Compare {self} vs [{other}]"),
+                );
+                Self::compare_lists(a, slice::from_ref(other), depth + 1, out)
+            }
+            (List(a), List(b)) => Self::compare_lists(a, b, depth, out),
+        }
+    }
+
+    fn compare_lists(
+        a: &[Packet],
+        b: &[Packet],
+        depth: usize,
+        mut out: Option<&mut String>,
+    ) -> Ordering {
+        for (a, b) in a.iter().zip(b) {
+            let ret = a.compare(b, depth + 1, out.as_deref_mut());
+            if ret.is_ne() {
+                return ret;
+            }
+        }
+        let ret = a.len().cmp(&b.len());
+        let verdict = match ret {
+            Ordering::Less => "Left side ran out of items, so inputs are in the right order",
+            Ordering::Greater => {
+                "Right side ran out of items, so inputs are not in the right order"
+            }
+            Ordering::Equal => return ret,
+        };
+        note(&mut out, depth + 1, format_args!("{verdict}"));
+        ret
+    }
+
+    // A trace of how cmp() decides the order of a pair of packets
+    fn explain(&self, other: &Self) -> String {
+        let mut ret = String::new();
+        if self.compare(other, 0, Some(&mut ret)).is_eq() {
+            writeln!(ret, "- Packets are equal, so their order is undecided").unwrap();
+        }
+        ret
+    }
+
+    // One item per line, indented by nesting depth
+    fn fmt_pretty(&self, f: &mut Formatter, depth: usize) -> fmt::Result {
+        match self {
+            Int(n) => write!(f, "{n}"),
+            List(items) if items.is_empty() => write!(f, "[]"),
+            List(items) => {
+                writeln!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{:1$}", "", 2 * (depth + 1))?;
+                    item.fmt_pretty(f, depth + 1)?;
+                    writeln!(f, "{}", if i + 1 < items.len() { "," } else { "" })?;
+                }
+                write!(f, "{:1$}]", "", 2 * depth)
+            }
         }
     }
 }
 
-fn packet_from_json(value: &JsonValue) -> Packet {
-    use json::JsonValue::*;
-    match value {
-        Array(objs) => List(objs.iter().map(packet_from_json).collect()),
-        Number(n) => Int(n.as_fixed_point_u64(0).unwrap()),
-        _ => unreachable!("How to parse {value:?}!?"),
+// "{}" gives the packet exactly as in the puzzle input, and "{:#}" gives a
+// pretty-printed form. Both parse back to the same packet.
+impl Display for Packet {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return self.fmt_pretty(f, 0);
+        }
+        match self {
+            Int(n) => write!(f, "{n}"),
+            List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+// Recursive-descent parser for the packet grammar:
+//
+//   packet = list
+//   list   = "[" [ value { "," value } ] "]"
+//   value  = list | int
+//   int    = digit { digit }
+//
+// Whitespace is allowed between tokens. Errors report the 1-based column.
+struct Parser<'a> {
+    line: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(line: &'a str) -> Self {
+        Self { line, pos: 0 }
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = &self.line[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.line[self.pos..].chars().next()
+    }
+
+    fn error(&self, expected: &str) -> Error {
+        let found = match self.line[self.pos..].chars().next() {
+            Some(c) => format!("{c:?}"),
+            None => "end of line".to_owned(),
+        };
+        anyhow!(
+            "Column {}: Expected {expected}, found {found}",
+            self.line[..self.pos].chars().count() + 1
+        )
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("{c:?}")));
+        }
+        self.pos += c.len_utf8();
+        Ok(())
+    }
+
+    fn packet(&mut self) -> Result<Packet> {
+        let ret = self.list()?;
+        if self.peek().is_some() {
+            return Err(self.error("end of line"));
+        }
+        Ok(ret)
+    }
+
+    fn list(&mut self) -> Result<Packet> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        if self.peek() != Some(']') {
+            items.push(self.value()?);
+            while self.peek() == Some(',') {
+                self.pos += 1;
+                items.push(self.value()?);
+            }
+        }
+        if self.peek() != Some(']') {
+            return Err(self.error("',' or ']'"));
+        }
+        self.pos += 1;
+        Ok(List(items))
+    }
+
+    fn value(&mut self) -> Result<Packet> {
+        match self.peek() {
+            Some('[') => self.list(),
+            Some(c) if c.is_ascii_digit() => Ok(self.int()),
+            _ => Err(self.error("'[' or a digit")),
+        }
+    }
+
+    fn int(&mut self) -> Packet {
+        let len = self.line[self.pos..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.line.len() - self.pos);
+        let digits = &self.line[self.pos..self.pos + len];
+        self.pos += len;
+        Int(Num::from_digits(digits))
     }
 }
 
@@ -59,36 +304,411 @@ impl FromStr for Packet {
     type Err = Error;
 
     fn from_str(line: &str) -> Result<Self, Self::Err> {
-        Ok(packet_from_json(&json::parse(line)?))
+        Parser::new(line).packet()
     }
 }
 
-fn main() {
-    let lines: Vec<_> = io::stdin().lines().map(Result::unwrap).collect();
-    let pairs: Vec<(Packet, Packet)> = lines
-        .split(String::is_empty)
-        .map(|lines| match lines {
-            [left, right] => (left.parse().unwrap(), right.parse().unwrap()),
-            _ => unreachable!("Failed to find packet pair: {lines:?}"),
+// Read pairs of packets, separated by blank lines, as they are needed
+fn read_pairs(lines: impl Iterator<Item = String>) -> impl Iterator<Item = (Packet, Packet)> {
+    let parse = |line: String| -> Packet {
+        line.parse()
+            .unwrap_or_else(|e| panic!("Bad packet {line:?}: {e}"))
+    };
+    lines.batching(move |lines| {
+        let left = lines.find(|line| !line.is_empty())?;
+        let right = lines
+            .next()
+            .filter(|line| !line.is_empty())
+            .unwrap_or_else(|| panic!("Failed to find a packet to pair with {left:?}"));
+        if let Some(line) = lines.next() {
+            assert!(line.is_empty(), "Expected a blank line, found {line:?}");
+        }
+        Some((parse(left), parse(right)))
+    })
+}
+
+// Where each divider would end up (1-based) if sorted along with the packets,
+// given how many packets are less than or equal to each divider. Dividers
+// go after any packets equal to them, and equal dividers keep their order.
+fn divider_indices(dividers: &[Packet], num_below: &[usize]) -> Vec<usize> {
+    dividers
+        .iter()
+        .enumerate()
+        .map(|(i, div)| {
+            let dividers_below = dividers
+                .iter()
+                .enumerate()
+                .filter(|&(j, other)| other < div || (other == div && j < i))
+                .count();
+            1 + num_below[i] + dividers_below
         })
-        .collect();
+        .collect()
+}
+
+// Writes packets to a file in sorted order. Packets are collected in chunks
+// of at most 'chunk_size', and if there is more than one chunk, each chunk is
+// sorted and written to a temporary "run" file, and the runs are merged at
+// the end. Equal packets keep their order.
+struct Sorter {
+    path: PathBuf,
+    chunk_size: usize,
+    chunk: Vec<Packet>,
+    runs: Vec<PathBuf>,
+}
+
+impl Sorter {
+    fn new(path: PathBuf, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        Self {
+            path,
+            chunk_size,
+            chunk: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    fn write_packet(out: &mut impl io::Write, packet: &Packet, pretty: bool) -> Result<()> {
+        if pretty {
+            writeln!(out, "{packet:#}")?;
+        } else {
+            writeln!(out, "{packet}")?;
+        }
+        Ok(())
+    }
+
+    fn write_sorted(path: &PathBuf, packets: &mut [Packet], pretty: bool) -> Result<()> {
+        packets.sort();
+        let mut out = BufWriter::new(File::create(path)?);
+        for packet in packets.iter() {
+            Self::write_packet(&mut out, packet, pretty)?;
+        }
+        Ok(out.flush()?)
+    }
 
-    println!(
-        "Part 1: {}",
-        pairs
+    // Sort the current chunk and write it out as a run
+    fn write_run(&mut self) -> Result<()> {
+        let mut run = self.path.clone().into_os_string();
+        run.push(format!(".run{}", self.runs.len()));
+        let run = PathBuf::from(run);
+        Self::write_sorted(&run, &mut self.chunk, false)?;
+        self.runs.push(run);
+        self.chunk.clear();
+        Ok(())
+    }
+
+    fn push(&mut self, packet: Packet) -> Result<()> {
+        self.chunk.push(packet);
+        if self.chunk.len() == self.chunk_size {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, pretty: bool) -> Result<()> {
+        if self.runs.is_empty() {
+            return Self::write_sorted(&self.path, &mut self.chunk, pretty);
+        }
+        if !self.chunk.is_empty() {
+            self.write_run()?;
+        }
+        let mut readers = self
+            .runs
             .iter()
-            .enumerate()
-            .map(|(i, (lhs, rhs))| if lhs < rhs { i + 1 } else { 0 })
-            .sum::<usize>()
-    );
-
-    let mut packets: Vec<_> = pairs.iter().flat_map(|(lhs, rhs)| [lhs, rhs]).collect();
-    let div2: Packet = "[[2]]".parse().unwrap();
-    let div6: Packet = "[[6]]".parse().unwrap();
-    packets.push(&div2);
-    packets.push(&div6);
-    packets.sort();
-    let i2 = packets.iter().position(|&p| p == &div2).unwrap();
-    let i6 = packets.iter().position(|&p| p == &div6).unwrap();
-    println!("Part 2: {}", (i2 + 1) * (i6 + 1));
+            .map(|run| Ok(BufReader::new(File::open(run)?).lines()))
+            .collect::<Result<Vec<_>>>()?;
+        let mut next = |i: usize| -> Result<Option<Reverse<(Packet, usize)>>> {
+            match readers[i].next() {
+                Some(line) => Ok(Some(Reverse((line?.parse()?, i)))),
+                None => Ok(None),
+            }
+        };
+        let mut heap = BinaryHeap::new();
+        for i in 0..self.runs.len() {
+            heap.extend(next(i)?);
+        }
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        while let Some(Reverse((packet, i))) = heap.pop() {
+            Self::write_packet(&mut out, &packet, pretty)?;
+            heap.extend(next(i)?);
+        }
+        out.flush()?;
+        for run in &self.runs {
+            fs::remove_file(run)?;
+        }
+        Ok(())
+    }
+}
+
+// Command-line options
+struct Options {
+    dividers: Vec<Packet>,   // --dividers LIST, separated by ';'
+    report_dividers: bool,   // print the indices of the dividers
+    sorted: Option<PathBuf>, // --sorted FILE: write the sorted packets here
+    pretty: bool,            // --pretty: pretty-print the sorted packets
+    chunk_size: usize,       // --chunk N: sort at most N packets in memory
+    explain_all: bool,       // --explain all
+    explain: Vec<usize>,     // --explain LIST of pairs (1-based, comma-separated)
+}
+
+impl Options {
+    fn from_args() -> Self {
+        let mut ret = Self {
+            dividers: vec!["[[2]]".parse().unwrap(), "[[6]]".parse().unwrap()],
+            report_dividers: false,
+            sorted: None,
+            pretty: false,
+            chunk_size: usize::MAX,
+            explain_all: false,
+            explain: Vec::new(),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| panic!("Missing value after {arg}"))
+            };
+            match arg.as_str() {
+                "--dividers" => {
+                    ret.dividers = value()
+                        .split(';')
+                        .map(|s| {
+                            s.parse()
+                                .unwrap_or_else(|e| panic!("Bad divider {s:?}: {e}"))
+                        })
+                        .collect();
+                    ret.report_dividers = true;
+                }
+                "--sorted" => ret.sorted = Some(value().into()),
+                "--pretty" => ret.pretty = true,
+                "--chunk" => ret.chunk_size = value().parse().unwrap(),
+                "--explain" => match value().as_str() {
+                    "all" => ret.explain_all = true,
+                    list => ret.explain = list.split(',').map(|n| n.parse().unwrap()).collect(),
+                },
+                _ => panic!("Unknown option {arg:?}"),
+            }
+        }
+        ret
+    }
+}
+
+fn main() {
+    let options = Options::from_args();
+    let dividers = &options.dividers;
+    let mut sorter = options
+        .sorted
+        .clone()
+        .map(|path| Sorter::new(path, options.chunk_size));
+
+    // Go through the pairs one at a time, so that we don't need to hold all
+    // of them in memory
+    let mut part1 = 0;
+    let mut num_below = vec![0; dividers.len()];
+    let mut num_pairs = 0;
+    let lines = io::stdin().lines().map(Result::unwrap);
+    for (i, (lhs, rhs)) in read_pairs(lines).enumerate() {
+        num_pairs += 1;
+        if options.explain_all || options.explain.contains(&(i + 1)) {
+            println!("== Pair {} ==\n{}", i + 1, lhs.explain(&rhs));
+        }
+        if lhs < rhs {
+            part1 += i + 1;
+        }
+        for (n, div) in num_below.iter_mut().zip(dividers) {
+            *n += (&lhs <= div) as usize + (&rhs <= div) as usize;
+        }
+        if let Some(sorter) = &mut sorter {
+            sorter.push(lhs).unwrap();
+            sorter.push(rhs).unwrap();
+        }
+    }
+    if let Some(i) = options.explain.iter().find(|&&i| i == 0 || i > num_pairs) {
+        panic!("No pair {i}");
+    }
+
+    println!("Part 1: {part1}");
+    let indices = divider_indices(dividers, &num_below);
+    println!("Part 2: {}", indices.iter().product::<usize>());
+    if options.report_dividers {
+        for (div, i) in dividers.iter().zip(&indices) {
+            println!("Divider {div} is at index {i}");
+        }
+    }
+
+    if let Some(mut sorter) = sorter {
+        for div in options.dividers {
+            sorter.push(div).unwrap();
+        }
+        sorter
+            .finish(options.pretty)
+            .expect("Failed to write sorted packets");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+[1,1,3,1,1]
+[1,1,5,1,1]
+
+[[1],[2,3,4]]
+[[1],4]
+
+[9]
+[[8,7,6]]
+
+[[4,4],4,4]
+[[4,4],4,4,4]
+
+[7,7,7,7]
+[7,7,7]
+
+[]
+[3]
+
+[[[]]]
+[[]]
+
+[1,[2,[3,[4,[5,6,7]]]],8,9]
+[1,[2,[3,[4,[5,6,0]]]],8,9]
+";
+
+    // Simple xorshift PRNG, to generate packets for tests
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn packet(&mut self, depth: usize) -> Packet {
+            let len = if depth == 0 { 0 } else { self.below(5) };
+            List(
+                (0..len)
+                    .map(|_| match self.below(4) {
+                        0 => self.packet(depth - 1),
+                        1 => Int(Num::from_digits(&"9".repeat(1 + self.below(30) as usize))),
+                        _ => Int(Num::from_digits(&self.below(11).to_string())),
+                    })
+                    .collect(),
+            )
+        }
+
+        // A packet that should compare equal to the given one, with integers
+        // randomly wrapped in (or unwrapped from) single-item lists
+        fn equivalent(&mut self, packet: &Packet) -> Packet {
+            let mut item = |p: &Packet| match p {
+                Int(_) if self.below(3) == 0 => List(vec![p.clone()]),
+                List(items) if self.below(2) == 0 && matches!(items[..], [Int(_)]) => {
+                    items[0].clone()
+                }
+                _ => self.equivalent(p),
+            };
+            match packet {
+                Int(_) => packet.clone(),
+                List(items) => List(items.iter().map(&mut item).collect()),
+            }
+        }
+    }
+
+    // Triples of random packets, where the second and third are sometimes
+    // equivalent to the one before
+    fn random_triples(n: usize) -> Vec<[Packet; 3]> {
+        let mut rng = Rng(0x2022_1213);
+        (0..n)
+            .map(|_| {
+                let a = rng.packet(3);
+                let b = match rng.below(3) {
+                    0 => rng.equivalent(&a),
+                    _ => rng.packet(3),
+                };
+                let c = match rng.below(3) {
+                    0 => rng.equivalent(&b),
+                    _ => rng.packet(3),
+                };
+                [a, b, c]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reflexive() {
+        for [a, _, _] in random_triples(10_000) {
+            assert!(a.cmp(&a).is_eq(), "{a}");
+        }
+    }
+
+    #[test]
+    fn antisymmetric() {
+        for [a, b, _] in random_triples(10_000) {
+            assert_eq!(a.cmp(&b), b.cmp(&a).reverse(), "{a} vs {b}");
+        }
+    }
+
+    #[test]
+    fn transitive() {
+        for triple in random_triples(10_000) {
+            for [a, b, c] in triple.iter().permutations(3).map(|p| [p[0], p[1], p[2]]) {
+                if a <= b && b <= c {
+                    assert!(a <= c, "{a} vs {b} vs {c}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn eq_agrees_with_cmp() {
+        for [a, b, _] in random_triples(10_000) {
+            assert_eq!(a == b, a.cmp(&b).is_eq(), "{a} vs {b}");
+            assert_eq!(a.partial_cmp(&b), Some(a.cmp(&b)), "{a} vs {b}");
+        }
+    }
+
+    #[test]
+    fn equivalent_packets_are_equal() {
+        let mut rng = Rng(0x2022_1213);
+        for _ in 0..10_000 {
+            let a = rng.packet(3);
+            let b = rng.equivalent(&a);
+            assert!(a == b && a.cmp(&b).is_eq(), "{a} vs {b}");
+        }
+    }
+
+    // Check that printing a packet (in either form) and parsing it again gives
+    // back the same packet, and the same text
+    fn check_round_trip(packet: &Packet) {
+        for text in [format!("{packet}"), format!("{packet:#}")] {
+            let parsed: Packet = text
+                .parse()
+                .unwrap_or_else(|e| panic!("Failed to re-parse {text:?}: {e}"));
+            assert_eq!(format!("{parsed:?}"), format!("{packet:?}"));
+            assert_eq!(format!("{parsed}"), format!("{packet}"));
+        }
+    }
+
+    #[test]
+    fn round_trip_example() {
+        for line in EXAMPLE.lines().filter(|line| !line.is_empty()) {
+            let packet: Packet = line.parse().unwrap();
+            assert_eq!(packet.to_string(), line);
+            check_round_trip(&packet);
+        }
+    }
+
+    #[test]
+    fn round_trip_random() {
+        let mut rng = Rng(0x2022_1213);
+        for _ in 0..10_000 {
+            check_round_trip(&rng.packet(4));
+        }
+    }
 }
@@ -1,15 +1,18 @@
 use std::cmp::{max, min};
-use std::collections::HashSet;
-use std::io;
+use std::io::Write;
 use std::ops::Add;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fs, io, thread};
 
 use anyhow::{anyhow, Error, Result};
+use itertools::Itertools;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 struct Pos {
-    x: u32,
-    y: u32,
+    x: i32,
+    y: i32,
 }
 
 impl Add for Pos {
@@ -29,8 +32,8 @@ impl FromStr for Pos {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.split_once(',') {
             Some((x, y)) => Ok(Self {
-                x: x.parse()?,
-                y: y.parse()?,
+                x: x.parse().map_err(|e| anyhow!("Bad x in {s:?}: {e}"))?,
+                y: y.parse().map_err(|e| anyhow!("Bad y in {s:?}: {e}"))?,
             }),
             _ => Err(anyhow!("Failed to parse {s:?}")),
         }
@@ -38,95 +41,247 @@ impl FromStr for Pos {
 }
 
 impl Pos {
-    fn line_between(&self, other: Pos) -> impl Iterator<Item = Pos> + '_ {
-        assert!(self.x == other.x || self.y == other.y);
-        assert!(self < &other);
-        (self.x..=other.x).flat_map(move |x| (self.y..=other.y).map(move |y| Pos { x, y }))
-    }
-
-    fn down(&self) -> Pos {
-        Pos {
-            x: self.x,
-            y: self.y + 1,
+    // All positions from self to other (inclusive), which must be a
+    // horizontal, vertical or 45° diagonal line. Diagonal lines also get the
+    // position beside each step, so that sand can't slip through them.
+    fn line_to(self, other: Pos) -> Result<Vec<Pos>> {
+        let (dx, dy) = (other.x - self.x, other.y - self.y);
+        if dx == 0 && dy == 0 {
+            return Err(anyhow!("Zero-length line at {self:?}"));
+        }
+        if dx != 0 && dy != 0 && dx.abs() != dy.abs() {
+            return Err(anyhow!(
+                "Line from {self:?} to {other:?} is not straight or 45°"
+            ));
+        }
+        let step = Pos {
+            x: dx.signum(),
+            y: dy.signum(),
+        };
+        let mut ret = vec![self];
+        let mut pos = self;
+        while pos != other {
+            if step.x != 0 && step.y != 0 {
+                ret.push(pos + Pos { x: step.x, y: 0 });
+            }
+            pos = pos + step;
+            ret.push(pos);
         }
+        Ok(ret)
     }
 
-    fn down_left(&self) -> Pos {
+    fn down_by(&self, dx: i32) -> Pos {
         Pos {
-            x: self.x - 1,
+            x: self.x + dx,
             y: self.y + 1,
         }
     }
+}
 
-    fn down_right(&self) -> Pos {
-        Pos {
-            x: self.x + 1,
-            y: self.y + 1,
+// How sand enters and moves through the cave
+#[derive(Debug)]
+struct Rules {
+    sources: Vec<Pos>,
+    order: Vec<usize>, // indices into 'sources', repeated while sand flows
+    moves: Vec<i32>,   // sideways steps to try (in order) with each step down
+    floor_depth: i32,  // distance from the lowest rock to the floor
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            sources: vec![Pos { x: 500, y: 0 }],
+            order: vec![0],
+            moves: vec![0, -1, 1],
+            floor_depth: 2,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cell {
+    Air,
+    Rock,
+    Sand,
+}
+
 #[derive(Debug)]
 struct Cave {
-    rocks: HashSet<Pos>,
-    start: Pos,
-    sand: HashSet<Pos>,
+    cells: Vec<Cell>, // row by row, covering min_x..min_x + width, min_y..min_y + height
+    min_x: i32,
+    min_y: i32, // 0, or higher up for sources above that
+    width: i32,
+    height: i32,
+    bottom: i32, // lowest rock
+    rules: Rules,
+    paths: Vec<Vec<Pos>>, // per source: where its previous grain fell until it rested
+    num_sand: usize,
 }
 
 impl Cave {
-    fn parse(lines: &[String], start: Pos) -> Self {
-        let mut rocks = HashSet::new();
-        for line in lines {
-            let corners: Vec<_> = line
+    fn parse(lines: &[String], rules: Rules) -> Result<Self> {
+        if rules.sources.is_empty() || rules.moves.is_empty() || rules.order.is_empty() {
+            return Err(anyhow!("Need at least one source, move and source index"));
+        }
+        if let Some(i) = rules.order.iter().find(|&&i| i >= rules.sources.len()) {
+            return Err(anyhow!("No source with index {i}"));
+        }
+        if rules.floor_depth < 1 {
+            return Err(anyhow!("The floor must be below the lowest rock"));
+        }
+        let mut rocks = Vec::new();
+        for (n, line) in lines.iter().enumerate() {
+            let corners = line
                 .split(" -> ")
-                .map(|s| s.parse::<Pos>().unwrap())
-                .collect();
-            for rock in corners.windows(2).flat_map(|ends| match ends {
-                [a, b] => min(a, b).line_between(*max(a, b)),
-                _ => unreachable!(".windows() failure!"),
-            }) {
-                rocks.insert(rock);
+                .map(|s| s.trim().parse::<Pos>())
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| anyhow!("Line {}: {e}", n + 1))?;
+            if corners.len() < 2 {
+                return Err(anyhow!("Line {}: A path needs at least two corners", n + 1));
+            }
+            for ends in corners.windows(2) {
+                let line = ends[0]
+                    .line_to(ends[1])
+                    .map_err(|e| anyhow!("Line {}: {e}", n + 1))?;
+                rocks.extend(line);
             }
         }
-        Self {
-            rocks,
-            start,
-            sand: HashSet::new(),
+        if rocks.is_empty() {
+            return Err(anyhow!("No rock paths in the input"));
+        }
+        // Sand moves at most 'spread' steps sideways per step down, so leave
+        // room for it to spread that far, down to the floor below the rocks
+        let bottom = rocks.iter().map(|pos| pos.y).max().unwrap();
+        let floor_y = bottom + rules.floor_depth;
+        if let Some(s) = rules.sources.iter().find(|s| s.y >= floor_y) {
+            return Err(anyhow!("Source {s:?} is not above the floor"));
+        }
+        let min_y = min(0, rules.sources.iter().map(|s| s.y).min().unwrap());
+        let height = floor_y + 1 - min_y;
+        let spread = rules.moves.iter().map(|dx| dx.abs()).max().unwrap();
+        let min_x = rocks
+            .iter()
+            .map(|pos| pos.x)
+            .chain(rules.sources.iter().map(|s| s.x - spread * (floor_y - s.y)))
+            .min()
+            .unwrap();
+        let max_x = rocks
+            .iter()
+            .map(|pos| pos.x)
+            .chain(rules.sources.iter().map(|s| s.x + spread * (floor_y - s.y)))
+            .max()
+            .unwrap();
+        let width = max_x - min_x + 1;
+        let mut ret = Self {
+            cells: vec![Cell::Air; (width * height) as usize],
+            min_x,
+            min_y,
+            width,
+            height,
+            bottom,
+            paths: Vec::new(),
+            rules,
+            num_sand: 0,
+        };
+        rocks.into_iter().for_each(|pos| ret.add_rock(pos));
+        // Sources inside rock are blocked from the start
+        ret.paths = ret
+            .rules
+            .sources
+            .iter()
+            .map(|&s| if ret.occupied(s) { vec![] } else { vec![s] })
+            .collect();
+        Ok(ret)
+    }
+
+    fn index(&self, pos: Pos) -> Option<usize> {
+        if pos.x < self.min_x
+            || pos.x >= self.min_x + self.width
+            || pos.y < self.min_y
+            || pos.y >= self.min_y + self.height
+        {
+            None
+        } else {
+            Some(((pos.y - self.min_y) * self.width + pos.x - self.min_x) as usize)
         }
     }
 
-    fn bottom(&self) -> u32 {
-        self.rocks.iter().map(|pos| pos.y).max().unwrap()
+    fn get(&self, pos: Pos) -> Cell {
+        self.index(pos).map_or(Cell::Air, |i| self.cells[i])
     }
 
-    fn _render(&self) {
-        let min_x = self.rocks.iter().map(|pos| pos.x).min().unwrap();
-        let max_x = self.rocks.iter().map(|pos| pos.x).max().unwrap();
-        let max_y = self.bottom();
-        for y in 0..=max_y {
+    fn set(&mut self, pos: Pos, cell: Cell) {
+        let i = self.index(pos).expect("Position outside cave!");
+        self.cells[i] = cell;
+    }
+
+    fn add_rock(&mut self, pos: Pos) {
+        self.set(pos, Cell::Rock);
+        self.bottom = max(self.bottom, pos.y);
+    }
+
+    fn add_floor(&mut self) {
+        let floor_y = self.bottom + self.rules.floor_depth;
+        for x in self.min_x..self.min_x + self.width {
+            self.add_rock(Pos { x, y: floor_y });
+        }
+    }
+
+    fn is_source(&self, pos: Pos) -> bool {
+        self.rules.sources.contains(&pos)
+    }
+
+    // Draw the part of the cave that holds rocks and sand, with the falling
+    // grain (if any) as '~'
+    fn render(&self, falling: Option<Pos>) -> String {
+        let (min_x, max_x) = (self.min_x..self.min_x + self.width)
+            .filter(|&x| (self.min_y..=self.bottom).any(|y| self.occupied(Pos { x, y })))
+            .chain(self.rules.sources.iter().map(|s| s.x))
+            .minmax()
+            .into_option()
+            .unwrap();
+        let mut ret = String::new();
+        for y in self.min_y..=self.bottom {
             for x in min_x..=max_x {
                 let p = Pos { x, y };
-                let c = if self.rocks.contains(&p) {
-                    '#'
-                } else if self.sand.contains(&p) {
-                    'o'
-                } else if p == self.start {
-                    '+'
-                } else {
-                    '.'
-                };
-                print!("{}", c);
+                ret.push(match self.get(p) {
+                    _ if Some(p) == falling => '~',
+                    Cell::Rock => '#',
+                    Cell::Sand => 'o',
+                    Cell::Air if self.is_source(p) => '+',
+                    Cell::Air => '.',
+                });
             }
-            println!();
+            ret.push('\n');
         }
+        ret
+    }
+
+    // Draw the whole cave as a binary PPM image, one pixel per position
+    fn to_ppm(&self, falling: Option<Pos>) -> Vec<u8> {
+        let mut ret = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for y in self.min_y..self.min_y + self.height {
+            for x in self.min_x..self.min_x + self.width {
+                let p = Pos { x, y };
+                ret.extend(match self.get(p) {
+                    _ if Some(p) == falling => [255, 64, 0],
+                    Cell::Rock => [128, 128, 128],
+                    Cell::Sand => [230, 200, 120],
+                    Cell::Air if self.is_source(p) => [255, 0, 0],
+                    Cell::Air => [16, 16, 32],
+                });
+            }
+        }
+        ret
     }
 
     fn occupied(&self, pos: Pos) -> bool {
-        self.rocks.contains(&pos) || self.sand.contains(&pos)
+        self.get(pos) != Cell::Air
     }
 
     fn fall(&self, pos: Pos) -> Pos {
-        for next in [pos.down(), pos.down_left(), pos.down_right()] {
+        for &dx in &self.rules.moves {
+            let next = pos.down_by(dx);
             if !self.occupied(next) {
                 return next;
             }
@@ -134,52 +289,330 @@ impl Cave {
         pos
     }
 
-    fn fall_until_rest(&self) -> Option<Pos> {
-        let mut cur = self.start;
+    // Has sand piled up all the way to this source?
+    fn blocked(&self, source: usize) -> bool {
+        self.paths[source].is_empty()
+    }
+
+    // Each grain follows the path of the previous grain from the same source
+    // until where that one rested, so pick up from just before that. Call
+    // 'show' after each step.
+    fn fall_until_rest_with(
+        &mut self,
+        source: usize,
+        show: &mut impl FnMut(&Self, Pos),
+    ) -> Option<Pos> {
+        let mut cur = *self.paths[source].last()?;
         loop {
             let next = self.fall(cur);
             if next == cur {
                 return Some(cur);
-            } else if next.y > self.bottom() {
+            } else if next.y > self.bottom {
                 return None;
             }
+            self.paths[source].push(next);
             cur = next;
+            show(self, cur);
+        }
+    }
+
+    // Count the positions that sand will fill when there is a floor: row by
+    // row, each position that is not rock gets sand if it is a source, or if
+    // sand can move into it from a position above that got sand
+    fn fill_from_sources(&self) -> usize {
+        let top = self.rules.sources.iter().map(|s| s.y).min().unwrap();
+        let mut filled = vec![false; self.width as usize];
+        let mut ret = 0;
+        for y in top..self.min_y + self.height {
+            let above = filled;
+            filled = (0..self.width as usize)
+                .map(|i| {
+                    let pos = Pos {
+                        x: self.min_x + i as i32,
+                        y,
+                    };
+                    self.get(pos) != Cell::Rock
+                        && (self.is_source(pos)
+                            || self.rules.moves.iter().any(|&dx| {
+                                let from = i as i32 - dx;
+                                0 <= from && from < self.width && above[from as usize]
+                            }))
+                })
+                .collect();
+            ret += filled.iter().filter(|&&f| f).count();
+        }
+        ret
+    }
+
+    fn rest(&mut self, source: usize, pos: Pos) {
+        assert_eq!(self.paths[source].pop(), Some(pos));
+        // Other sources' grains can no longer pass through here
+        for path in self.paths.iter_mut() {
+            if let Some(i) = path.iter().position(|&p| p == pos) {
+                path.truncate(i);
+            }
+        }
+        self.set(pos, Cell::Sand);
+        self.num_sand += 1;
+    }
+
+    // Pour sand from the sources in order until it flows into the abyss, or
+    // until every source is blocked
+    fn pour(&mut self, animation: &mut Option<Animation>) {
+        let order = self.rules.order.clone();
+        for &source in order.iter().cycle() {
+            if (0..self.paths.len()).all(|s| self.blocked(s)) {
+                return;
+            }
+            if self.blocked(source) {
+                continue;
+            }
+            let rested = match animation {
+                Some(animation) => {
+                    self.fall_until_rest_with(source, &mut |cave, pos| animation.step(cave, pos))
+                }
+                None => self.fall_until_rest_with(source, &mut |_, _| {}),
+            };
+            let Some(pos) = rested else {
+                return;
+            };
+            self.rest(source, pos);
+            if let Some(animation) = animation {
+                animation.rested(self);
+            }
+        }
+    }
+}
+
+// Shows the cave whenever a grain comes to rest (and optionally after each
+// step of its fall), in the terminal and/or as PPM images named after the
+// grain, e.g. grain000012.ppm, preceded by grain000012-0001.ppm etc. for
+// the steps of its fall
+struct Animation {
+    delay: Option<Duration>, // between frames in the terminal, if shown there
+    frame_dir: Option<PathBuf>,
+    every: usize, // only show every Nth grain
+    steps: bool,
+    step: usize, // of the falling grain
+}
+
+impl Animation {
+    fn new(delay: Option<Duration>, frame_dir: Option<PathBuf>, every: usize, steps: bool) -> Self {
+        if let Some(dir) = &frame_dir {
+            fs::create_dir_all(dir).expect("Failed to create frames directory");
+        }
+        if delay.is_some() {
+            // Clear the screen once; frames only grow as the sand spreads, so each
+            // one covers the one before
+            print!("\x1b[2J");
+        }
+        Self {
+            delay,
+            frame_dir,
+            every,
+            steps,
+            step: 0,
         }
     }
 
-    fn rest(&mut self, pos: Pos) {
-        self.sand.insert(pos);
+    fn step(&mut self, cave: &Cave, falling: Pos) {
+        let grain = cave.num_sand + 1;
+        if self.steps && grain % self.every == 0 {
+            self.step += 1;
+            let name = format!("grain{grain:06}-{:04}", self.step);
+            self.show(cave, Some(falling), &name);
+        }
+    }
+
+    fn rested(&mut self, cave: &Cave) {
+        self.step = 0;
+        if cave.num_sand % self.every == 0 {
+            self.show(cave, None, &format!("grain{:06}", cave.num_sand));
+        }
+    }
+
+    fn show(&self, cave: &Cave, falling: Option<Pos>, name: &str) {
+        if let Some(delay) = self.delay {
+            // Draw over the previous frame, from the top left corner
+            let frame = cave.render(falling);
+            print!("\x1b[HGrains at rest: {}\n{frame}", cave.num_sand);
+            io::stdout().flush().unwrap();
+            thread::sleep(delay);
+        }
+        if let Some(dir) = &self.frame_dir {
+            let path = dir.join(format!("{name}.ppm"));
+            fs::write(path, cave.to_ppm(falling)).expect("Failed to write frame");
+        }
     }
 }
 
 fn main() {
-    let lines: Vec<_> = io::stdin().lines().map(Result::unwrap).collect();
-    let mut cave = Cave::parse(&lines, Pos { x: 500, y: 0 });
-
-    while let Some(pos) = cave.fall_until_rest() {
-        cave.rest(pos)
-    }
-    // cave._render();
-    println!("Part 1: {}", cave.sand.len());
-
-    let floor_y = cave.bottom() + 2;
-    let floor_l = Pos {
-        x: cave.start.x - floor_y,
-        y: floor_y,
-    };
-    let floor_r = Pos {
-        x: cave.start.x + floor_y,
-        y: floor_y,
-    };
-    floor_l.line_between(floor_r).for_each(|pos| {
-        cave.rocks.insert(pos);
-    });
-    while let Some(pos) = cave.fall_until_rest() {
-        cave.rest(pos);
-        if pos == cave.start {
-            break;
+    // Optionally watch the sand fill the cave (--animate, --fps N) or dump it as
+    // PPM images (--frames DIR), showing only every Nth grain (--every N), and
+    // each step of their fall (--steps).
+    // Pour sand from other sources (--sources X,Y;X,Y...) in a given order of
+    // source indices (--order 0,1,1...), with sideways steps to try with each
+    // step down (--moves 0,-1,1), and the part 2 floor at another distance below
+    // the lowest rock (--floor N).
+    let mut rules = Rules::default();
+    let mut order = None;
+    let mut animate = false;
+    let mut fps = 10.0;
+    let mut frame_dir: Option<PathBuf> = None;
+    let mut every = 1;
+    let mut steps = false;
+    let numbers =
+        |list: &str| -> Vec<i32> { list.split(',').map(|n| n.trim().parse().unwrap()).collect() };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("Missing value after {arg}"))
+        };
+        match arg.as_str() {
+            "--sources" => rules.sources = value().split(';').map(|s| s.parse().unwrap()).collect(),
+            "--order" => order = Some(numbers(&value()).into_iter().map(|i| i as usize).collect()),
+            "--moves" => rules.moves = numbers(&value()),
+            "--floor" => rules.floor_depth = value().parse().unwrap(),
+            "--animate" => animate = true,
+            "--fps" => fps = value().parse().unwrap(),
+            "--frames" => frame_dir = Some(PathBuf::from(value())),
+            "--every" => every = value().parse().unwrap(),
+            "--steps" => steps = true,
+            _ => panic!("Unknown option {arg:?}"),
         }
     }
-    // cave._render();
-    println!("Part 2: {}", cave.sand.len());
+    // Without an order, pour from each source in turn
+    rules.order = order.unwrap_or_else(|| (0..rules.sources.len()).collect());
+    let mut animation = (animate || frame_dir.is_some()).then(|| {
+        let delay = animate.then(|| Duration::from_secs_f64(1.0 / fps));
+        Animation::new(delay, frame_dir, every, steps)
+    });
+
+    let lines: Vec<_> = io::stdin().lines().map(Result::unwrap).collect();
+    let mut cave = Cave::parse(&lines, rules).unwrap();
+
+    cave.pour(&mut animation);
+    let part1 = cave.num_sand;
+
+    cave.add_floor();
+    let part2 = cave.fill_from_sources();
+    // Counting part 2 doesn't need every grain, but watching it does
+    if animation.is_some() {
+        cave.pour(&mut animation);
+    }
+
+    println!("Part 1: {part1}");
+    println!("Part 2: {part2}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+498,4 -> 498,6 -> 496,6
+503,4 -> 502,4 -> 502,9 -> 494,9
+";
+
+    // Count the sand with a floor by filling row by row, and check that
+    // pouring every grain gives the same count
+    fn fill_and_pour(input: &str, rules: Rules) -> usize {
+        let lines: Vec<String> = input.lines().map(str::to_owned).collect();
+        let mut cave = Cave::parse(&lines, rules).unwrap();
+        cave.add_floor();
+        let filled = cave.fill_from_sources();
+        cave.pour(&mut None);
+        assert_eq!(cave.num_sand, filled);
+        filled
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(fill_and_pour(EXAMPLE, Rules::default()), 93);
+    }
+
+    #[test]
+    fn input() {
+        assert_eq!(
+            fill_and_pour(include_str!("14.input"), Rules::default()),
+            27539
+        );
+    }
+
+    #[test]
+    fn other_rules() {
+        let rules = Rules {
+            sources: vec![Pos { x: 500, y: 0 }, Pos { x: 497, y: 2 }],
+            order: vec![0, 1, 1],
+            moves: vec![0, 1, -2],
+            floor_depth: 3,
+        };
+        fill_and_pour(EXAMPLE, rules);
+    }
+
+    #[test]
+    fn source_above_row_0() {
+        let rules = Rules {
+            sources: vec![Pos { x: 2, y: -2 }],
+            order: vec![0],
+            floor_depth: 1,
+            ..Rules::default()
+        };
+        assert_eq!(fill_and_pour("0,0 -> 3,3", rules), 17);
+    }
+
+    // The V holds 12 grains inside it and a pyramid of 16 on top
+    #[test]
+    fn diagonal_lines_hold_sand() {
+        let rules = Rules {
+            sources: vec![Pos { x: 4, y: 0 }],
+            ..Rules::default()
+        };
+        let lines = vec!["0,5 -> 4,9 -> 8,5".to_owned()];
+        let mut cave = Cave::parse(&lines, rules).unwrap();
+        cave.pour(&mut None);
+        assert_eq!(cave.num_sand, 28);
+    }
+
+    #[test]
+    fn bad_input() {
+        let parse = |input: &str, rules| {
+            let lines: Vec<String> = input.lines().map(str::to_owned).collect();
+            Cave::parse(&lines, rules)
+        };
+        assert!(parse(EXAMPLE, Rules::default()).is_ok());
+        assert!(parse("", Rules::default()).is_err());
+        assert!(parse("1,1", Rules::default()).is_err());
+        assert!(parse("1,1 -> 1,1", Rules::default()).is_err());
+        assert!(parse("1,1 -> 2,3", Rules::default()).is_err());
+        assert!(parse("1,1 -> 1,x", Rules::default()).is_err());
+        let order = vec![0, 1];
+        assert!(parse(
+            EXAMPLE,
+            Rules {
+                order,
+                ..Rules::default()
+            }
+        )
+        .is_err());
+        let floor_depth = -3;
+        assert!(parse(
+            EXAMPLE,
+            Rules {
+                floor_depth,
+                ..Rules::default()
+            }
+        )
+        .is_err());
+        let sources = vec![Pos { x: 500, y: 20 }];
+        assert!(parse(
+            EXAMPLE,
+            Rules {
+                sources,
+                ..Rules::default()
+            }
+        )
+        .is_err());
+    }
 }
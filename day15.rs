@@ -1,8 +1,11 @@
-use std::io;
+use std::cmp::{max, min};
+use std::fmt;
 use std::ops::Add;
 use std::str::FromStr;
+use std::{env, fs, io};
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
+use itertools::Itertools;
 use text_io::scan;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
@@ -29,7 +32,7 @@ impl Add for Pos {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Sensor {
     pos: Pos,
     beacon: Pos,
@@ -48,25 +51,34 @@ impl FromStr for Sensor {
     }
 }
 
+impl fmt::Display for Sensor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Sensor at x={}, y={}: closest beacon is at x={}, y={}",
+            self.pos.x, self.pos.y, self.beacon.x, self.beacon.y
+        )
+    }
+}
+
 impl Sensor {
     fn radius(&self) -> u32 {
         self.pos.mdist(self.beacon)
     }
 
-    fn bounds(&self) -> [Pos; 4] {
-        [
-            self.pos + Pos::new(-(self.radius() as i32), 0),
-            self.pos + Pos::new(0, -(self.radius() as i32)),
-            self.pos + Pos::new(self.radius() as i32, 0),
-            self.pos + Pos::new(0, self.radius() as i32),
-        ]
-    }
-
     fn within(&self, pos: Pos) -> bool {
         // pos is within self's radius
         self.pos.mdist(pos) <= self.radius()
     }
 
+    // The diagonal lines just outside self's radius, in rotated coordinates:
+    // Two lines of constant u = x + y, and two of constant v = x - y
+    fn outside_lines(&self) -> ([i32; 2], [i32; 2]) {
+        let (u, v) = (self.pos.x + self.pos.y, self.pos.x - self.pos.y);
+        let r = self.radius() as i32 + 1;
+        ([u - r, u + r], [v - r, v + r])
+    }
+
     fn x_run(&self, y: i32) -> Option<(i32, i32)> {
         let remains = (self.radius() as i32) - (self.pos.y.abs_diff(y) as i32);
         if remains >= 0 {
@@ -77,6 +89,95 @@ impl Sensor {
     }
 }
 
+// The x ranges (inclusive) covered by sensors at row y, sorted and merged so
+// that they neither overlap nor touch
+fn merged_x_runs(sensors: &[Sensor], y: i32) -> Vec<(i32, i32)> {
+    let mut x_runs: Vec<(i32, i32)> = sensors.iter().filter_map(|s| s.x_run(y)).collect();
+    x_runs.sort();
+    let mut ret: Vec<(i32, i32)> = Vec::new();
+    for (a, b) in x_runs {
+        match ret.last_mut() {
+            Some((_, end)) if a <= *end + 1 => *end = max(*end, b),
+            _ => ret.push((a, b)),
+        }
+    }
+    ret
+}
+
+// The x ranges (inclusive) within x_range at row y that no sensor covers.
+// Sensors and beacons never fall in these gaps: each one lies within its own
+// sensor's x_run.
+fn uncovered_x_runs(sensors: &[Sensor], y: i32, x_range: (i32, i32)) -> Vec<(i32, i32)> {
+    let mut ret = Vec::new();
+    let mut x = x_range.0;
+    let covered = merged_x_runs(sensors, y).into_iter();
+    for (a, b) in covered.chain([(x_range.1 + 1, x_range.1 + 1)]) {
+        if x < a {
+            ret.push((x, min(a - 1, x_range.1)));
+        }
+        x = max(x, b + 1);
+        if x > x_range.1 {
+            break;
+        }
+    }
+    ret
+}
+
+// Every uncovered position within 'area' (corners inclusive), as x ranges
+// and the range of consecutive rows sharing that same x range
+fn uncovered_regions(sensors: &[Sensor], area: (Pos, Pos)) -> Vec<((i32, i32), (i32, i32))> {
+    let (top_left, bottom_right) = area;
+    let x_range = (top_left.x, bottom_right.x);
+    let mut ret: Vec<((i32, i32), (i32, i32))> = Vec::new();
+    let mut open: Vec<usize> = Vec::new(); // regions reaching the previous row
+    for y in top_left.y..=bottom_right.y {
+        let runs = uncovered_x_runs(sensors, y, x_range);
+        let mut still_open = Vec::new();
+        for &i in &open {
+            let (x_run, y_run) = &mut ret[i];
+            if runs.contains(x_run) {
+                y_run.1 = y;
+                still_open.push(i);
+            }
+        }
+        for x_run in runs {
+            if !still_open.iter().any(|&i| ret[i].0 == x_run) {
+                still_open.push(ret.len());
+                ret.push((x_run, (y, y)));
+            }
+        }
+        open = still_open;
+    }
+    ret
+}
+
+// Greedily propose new sensors with the given radius until every position in
+// 'area' is covered. Each new sensor covers the first uncovered run of
+// positions (in reading order) with its upper left edge, or as much of that
+// run as it can with its top corner, as everything above is already covered.
+fn advise_sensors(sensors: &[Sensor], area: (Pos, Pos), radius: u32) -> Vec<Sensor> {
+    let (top_left, bottom_right) = area;
+    let mut all: Vec<Sensor> = sensors.to_vec();
+    let mut ret = Vec::new();
+    let mut y = top_left.y;
+    while y <= bottom_right.y {
+        match uncovered_x_runs(&all, y, (top_left.x, bottom_right.x)).first() {
+            Some(&(x, end)) => {
+                let d = min(radius as i32, (end - x + 1) / 2);
+                let pos = Pos::new(x + d, y + radius as i32 - d);
+                let new = Sensor {
+                    pos,
+                    beacon: pos + Pos::new(radius as i32, 0),
+                };
+                all.push(new.clone());
+                ret.push(new);
+            }
+            None => y += 1,
+        }
+    }
+    ret
+}
+
 fn first_uncovered_pos_at_row(sensors: &[Sensor], y: i32, x_range: (i32, i32)) -> Option<Pos> {
     let (mut x, x_max) = x_range;
     let mut x_runs: Vec<(i32, i32)> = sensors.iter().filter_map(|s| s.x_run(y)).collect();
@@ -92,7 +193,171 @@ fn first_uncovered_pos_at_row(sensors: &[Sensor], y: i32, x_range: (i32, i32)) -
     Some(Pos::new(x, y))
 }
 
+// Find an uncovered position with 0 <= x, y <= bound, by looking where the
+// lines just outside the sensors' radii cross each other and the edges of the
+// search area. A lone uncovered position usually lies on such a crossing, but
+// not always: if every sensor next to it leaves it on parallel lines, it is
+// missed and this returns None.
+fn uncovered_pos_in_square(sensors: &[Sensor], bound: i32) -> Option<Pos> {
+    let (u_lines, v_lines): (Vec<_>, Vec<_>) = sensors.iter().map(|s| s.outside_lines()).unzip();
+    let mut u_lines: Vec<i32> = u_lines.into_iter().flatten().collect();
+    let mut v_lines: Vec<i32> = v_lines.into_iter().flatten().collect();
+    // The edges of the search area, as seen from the diagonals
+    u_lines.extend([0, bound, 2 * bound]);
+    v_lines.extend([-bound, 0, bound]);
+    let mut candidates = Vec::new();
+    for (&u, &v) in u_lines.iter().cartesian_product(v_lines.iter()) {
+        if (u + v) % 2 == 0 {
+            candidates.push(Pos::new((u + v) / 2, (u - v) / 2));
+        }
+    }
+    for &u in &u_lines {
+        candidates.extend([Pos::new(0, u), Pos::new(bound, u - bound)]);
+        candidates.extend([Pos::new(u, 0), Pos::new(u - bound, bound)]);
+    }
+    for &v in &v_lines {
+        candidates.extend([Pos::new(0, -v), Pos::new(bound, bound - v)]);
+        candidates.extend([Pos::new(v, 0), Pos::new(v + bound, bound)]);
+    }
+    candidates
+        .into_iter()
+        .filter(|p| 0 <= p.x && p.x <= bound && 0 <= p.y && p.y <= bound)
+        .sorted()
+        .find(|&p| !sensors.iter().any(|s| s.within(p)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pixel {
+    Uncovered,
+    Covered(usize), // index of first sensor covering this pixel
+    Sensor,
+    Beacon,
+    Gap, // the uncovered position
+}
+
+// Map the area 0..=bound with 'scale' x 'scale' positions per pixel. Each
+// pixel shows whether its top left position is covered (and by which sensor,
+// in the order they are given), and sensors, beacons and the uncovered
+// position are marked on top.
+fn coverage_map(sensors: &[Sensor], bound: i32, scale: i32, gap: Option<Pos>) -> Vec<Vec<Pixel>> {
+    let size = (bound / scale + 1) as usize;
+    let mut ret: Vec<Vec<Pixel>> = (0..size)
+        .map(|py| {
+            (0..size)
+                .map(|px| {
+                    let pos = Pos::new(px as i32 * scale, py as i32 * scale);
+                    match sensors.iter().position(|s| s.within(pos)) {
+                        Some(i) => Pixel::Covered(i),
+                        None => Pixel::Uncovered,
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let markers = sensors
+        .iter()
+        .map(|s| (s.pos, Pixel::Sensor))
+        .chain(sensors.iter().map(|s| (s.beacon, Pixel::Beacon)))
+        .chain(gap.map(|pos| (pos, Pixel::Gap)));
+    for (pos, pixel) in markers {
+        if 0 <= pos.x && pos.x <= bound && 0 <= pos.y && pos.y <= bound {
+            ret[(pos.y / scale) as usize][(pos.x / scale) as usize] = pixel;
+        }
+    }
+    ret
+}
+
+fn to_ascii(map: &[Vec<Pixel>]) -> String {
+    map.iter()
+        .map(|row| {
+            row.iter()
+                .map(|pixel| match pixel {
+                    Pixel::Uncovered => '.',
+                    Pixel::Covered(_) => '#',
+                    Pixel::Sensor => 'S',
+                    Pixel::Beacon => 'B',
+                    Pixel::Gap => '*',
+                })
+                .collect::<String>()
+                + "\n"
+        })
+        .collect()
+}
+
+// Write a binary PPM image, with each sensor's area in its own shade
+fn to_ppm(map: &[Vec<Pixel>], num_sensors: usize) -> Vec<u8> {
+    let mut ret = format!("P6\n{} {}\n255\n", map[0].len(), map.len()).into_bytes();
+    for pixel in map.iter().flatten() {
+        ret.extend(match pixel {
+            Pixel::Uncovered => [0, 0, 0],
+            Pixel::Covered(i) => {
+                let shade = (64 + 160 * i / max(1, num_sensors - 1)) as u8;
+                [shade, shade, shade]
+            }
+            Pixel::Sensor => [255, 0, 0],
+            Pixel::Beacon => [0, 0, 255],
+            Pixel::Gap => [0, 255, 0],
+        });
+    }
+    ret
+}
+
+// An area given by two corners, as X0,Y0,X1,Y1
+struct Area((Pos, Pos));
+
+impl FromStr for Area {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let corners = s
+            .split(',')
+            .map(|n| n.trim().parse())
+            .collect::<Result<Vec<i32>, _>>()?;
+        match corners[..] {
+            [x0, y0, x1, y1] => Ok(Self((Pos::new(x0, y0), Pos::new(x1, y1)))),
+            _ => Err(anyhow!("Expected X0,Y0,X1,Y1, not {s:?}")),
+        }
+    }
+}
+
 fn main() {
+    // Optionally count covered positions at another row than y=2000000 (--row Y),
+    // search another area than 0..=4000000 (--bound N), or search for the
+    // uncovered position by scanning every row (--scan-rows).
+    // Draw a map of the search area as ASCII (--ascii) or as a PPM image
+    // (--ppm FILE), with --scale N positions per pixel. List every uncovered
+    // region within an area given by its corners (--gaps X0,Y0,X1,Y1), or
+    // propose new sensors of a given radius to cover them all (--advise
+    // X0,Y0,X1,Y1 --radius R).
+    let mut row = 2_000_000;
+    let mut bound = 4_000_000;
+    let mut scan = false;
+    let mut ascii = false;
+    let mut ppm_path = None;
+    let mut scale = None;
+    let mut gaps = None;
+    let mut advise = None;
+    let mut radius = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("Missing value after {arg}"))
+        };
+        match arg.as_str() {
+            "--row" => row = value().parse().unwrap(),
+            "--bound" => bound = value().parse().unwrap(),
+            "--scan-rows" => scan = true,
+            "--ascii" => ascii = true,
+            "--ppm" => ppm_path = Some(value()),
+            "--scale" => scale = Some(value().parse().unwrap()),
+            "--gaps" => gaps = Some(value().parse::<Area>().unwrap()),
+            "--advise" => advise = Some(value().parse::<Area>().unwrap()),
+            "--radius" => radius = Some(value().parse().unwrap()),
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
+
     let mut sensors: Vec<Sensor> = io::stdin()
         .lines()
         .map(Result::unwrap)
@@ -103,31 +368,120 @@ fn main() {
     // sensors.sort_by_key(|s| -(s.radius() as i32));
     sensors.sort_by_key(|s| s.pos.x - s.radius() as i32);
 
-    let min_x = sensors
-        .iter()
-        .flat_map(|s| s.bounds())
-        .map(|p| p.x)
-        .min()
-        .unwrap();
-    let max_x = sensors
+    // Positions covered by sensors, except where we know there is a beacon
+    let runs = merged_x_runs(&sensors, row);
+    let covered: u64 = runs.iter().map(|(a, b)| b.abs_diff(*a) as u64 + 1).sum();
+    let beacons = sensors
         .iter()
-        .flat_map(|s| s.bounds())
-        .map(|p| p.x)
-        .max()
-        .unwrap();
-
-    println!(
-        "Part 1: {}",
-        (min_x..=max_x)
-            .map(|x| Pos::new(x, 2_000_000))
-            .filter(|p| sensors.iter().any(|s| s.within(*p) && s.beacon != *p))
-            .count()
-    );
-
-    for y in 0..=4_000_000 {
-        if let Some(pos) = first_uncovered_pos_at_row(&sensors, y, (0, 4_000_000)) {
-            println!("Part 2: {}", pos.x as u64 * 4_000_000u64 + pos.y as u64);
-            return;
+        .map(|s| s.beacon)
+        .filter(|b| b.y == row && runs.iter().any(|(a, c)| *a <= b.x && b.x <= *c))
+        .unique()
+        .count();
+    println!("Part 1: {}", covered - beacons as u64);
+
+    let scan_rows =
+        || (0..=bound).find_map(|y| first_uncovered_pos_at_row(&sensors, y, (0, bound)));
+    let uncovered = if scan {
+        scan_rows()
+    } else {
+        uncovered_pos_in_square(&sensors, bound).or_else(|| {
+            eprintln!("No uncovered position where boundary lines cross, scanning rows");
+            scan_rows()
+        })
+    };
+    match uncovered {
+        Some(pos) => println!("Part 2: {}", pos.x as u64 * 4_000_000u64 + pos.y as u64),
+        None => eprintln!("Part 2: No uncovered position with 0 <= x, y <= {bound}"),
+    }
+
+    if let Some(Area(area)) = gaps {
+        for ((xa, xb), (ya, yb)) in uncovered_regions(&sensors, area) {
+            println!("Uncovered: x={xa}..={xb}, y={ya}..={yb}");
+        }
+    }
+    if let Some(Area(area)) = advise {
+        let radius = radius.expect("Missing --radius for new sensors");
+        for s in advise_sensors(&sensors, area, radius) {
+            println!("{s}");
+        }
+    }
+
+    let scale = |pixels: i32| scale.unwrap_or(max(1, bound / pixels + 1));
+    if ascii {
+        print!(
+            "{}",
+            to_ascii(&coverage_map(&sensors, bound, scale(80), uncovered))
+        );
+    }
+    if let Some(path) = ppm_path {
+        let map = coverage_map(&sensors, bound, scale(800), uncovered);
+        fs::write(path, to_ppm(&map, sensors.len())).expect("Failed to write PPM file");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+Sensor at x=2, y=18: closest beacon is at x=-2, y=15
+Sensor at x=9, y=16: closest beacon is at x=10, y=16
+Sensor at x=13, y=2: closest beacon is at x=15, y=3
+Sensor at x=12, y=14: closest beacon is at x=10, y=16
+Sensor at x=10, y=20: closest beacon is at x=10, y=16
+Sensor at x=14, y=17: closest beacon is at x=10, y=16
+Sensor at x=8, y=7: closest beacon is at x=2, y=10
+Sensor at x=2, y=0: closest beacon is at x=2, y=10
+Sensor at x=0, y=11: closest beacon is at x=2, y=10
+Sensor at x=20, y=14: closest beacon is at x=25, y=17
+Sensor at x=17, y=20: closest beacon is at x=21, y=22
+Sensor at x=16, y=7: closest beacon is at x=15, y=3
+Sensor at x=14, y=3: closest beacon is at x=15, y=3
+Sensor at x=20, y=1: closest beacon is at x=15, y=3
+";
+
+    fn example() -> Vec<Sensor> {
+        EXAMPLE.lines().map(|line| line.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn uncovered_pos() {
+        let sensors = example();
+        let scanned = (0..=20).find_map(|y| first_uncovered_pos_at_row(&sensors, y, (0, 20)));
+        assert_eq!(scanned, Some(Pos::new(14, 11)));
+        assert_eq!(uncovered_pos_in_square(&sensors, 20), scanned);
+    }
+
+    #[test]
+    fn gaps() {
+        let area = (Pos::new(0, 0), Pos::new(20, 20));
+        assert_eq!(uncovered_regions(&example(), area), [((14, 14), (11, 11))]);
+
+        // Further out, no gap holds a sensor or beacon
+        let sensors = example();
+        let area = (Pos::new(-10, -10), Pos::new(30, 30));
+        let regions = uncovered_regions(&sensors, area);
+        assert!(!regions.is_empty());
+        for ((xa, xb), (ya, yb)) in regions {
+            for p in sensors.iter().flat_map(|s| [s.pos, s.beacon]) {
+                assert!(!(xa <= p.x && p.x <= xb && ya <= p.y && p.y <= yb));
+            }
+        }
+    }
+
+    #[test]
+    fn advice_covers_gaps() {
+        let areas = [
+            (Pos::new(0, 0), Pos::new(20, 20)),
+            (Pos::new(-10, -10), Pos::new(30, 30)),
+        ];
+        for (area, radius) in areas.into_iter().zip([2, 3]) {
+            let mut sensors = example();
+            let advice = advise_sensors(&sensors, area, radius);
+            assert!(!advice.is_empty());
+            assert!(advice.iter().all(|s| s.radius() == radius));
+            sensors.extend(advice);
+            assert_eq!(uncovered_regions(&sensors, area), []);
         }
     }
 }
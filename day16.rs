@@ -1,7 +1,8 @@
 use std::cmp::max;
 use std::collections::{HashMap, HashSet};
-use std::io;
+use std::fmt::Write;
 use std::str::FromStr;
+use std::{env, fs, io};
 
 use anyhow::{Error, Result};
 use itertools::Itertools;
@@ -55,8 +56,32 @@ impl OpenValves {
     }
 }
 
+// The valves visited (starting with the start valve) and the valves opened,
+// in order, by a single player
+#[derive(Debug)]
+struct Route {
+    path: Vec<u32>,
+    opened: Vec<u32>,
+}
+
+impl Route {
+    fn new(start: u32) -> Self {
+        Self {
+            path: vec![start],
+            opened: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Solution {
+    score: u32,
+    routes: Vec<Route>,
+}
+
 struct World {
     valve_index_by_name: HashMap<String, u32>,
+    valve_names: Vec<String>,
     flow_map: Vec<u32>,
     conn_map: Vec<Vec<u32>>,
     state_v_bits: usize,
@@ -82,6 +107,8 @@ impl World {
             .map(|(i, v)| (v.name.to_owned(), i as u32))
             .collect();
 
+        let valve_names = parsed_valves.iter().map(|v| v.name.to_owned()).collect();
+
         // Build flow map and connections map based on valve indices
         let flow_map: Vec<u32> = parsed_valves.iter().map(|v| v.flow).collect();
         let conn_map = parsed_valves
@@ -111,6 +138,7 @@ impl World {
 
         Self {
             valve_index_by_name,
+            valve_names,
             flow_map,
             conn_map,
             state_v_bits,
@@ -121,7 +149,7 @@ impl World {
     }
 
     fn get(&self, name: &str) -> u32 {
-        *self.valve_index_by_name.get(name).unwrap() as u32
+        *self.valve_index_by_name.get(name).unwrap()
     }
 
     fn state(&self, v: u32, opened: OpenValves, t: u32, player: usize) -> usize {
@@ -141,7 +169,7 @@ impl World {
             | player << (self.state_v_bits + self.state_t_bits + self.state_opened_bits)
     }
 
-    fn run(&self, start: u32, opened: OpenValves, t0: u32, player: usize) -> u32 {
+    fn run(&self, start: u32, opened: OpenValves, t0: u32, player: usize) -> Solution {
         let bits_needed =
             self.state_v_bits + self.state_opened_bits + self.state_t_bits + self.state_player_bits;
         let mut states: Vec<u32> = vec![0; 1 << bits_needed];
@@ -195,7 +223,119 @@ impl World {
             ret
         }
 
-        score(start, opened, t0, player, &mut states, &(self, start, t0))
+        let conditions = (self, start, t0);
+        let best = score(start, opened, t0, player, &mut states, &conditions);
+
+        // Retrace the best solution by replaying the choices that reproduce
+        // the recorded scores. Stop each player's route once the remaining
+        // players can do just as well without them.
+        let mut routes = vec![Route::new(start)];
+        let (mut v, mut opened, mut t, mut player) = (start, opened, t0, player);
+        loop {
+            let here = score(v, opened, t, player, &mut states, &conditions);
+            let rest = match player {
+                0 => 0,
+                _ => score(start, opened, t0, player - 1, &mut states, &conditions),
+            };
+            if t == 0 || here == rest {
+                if player == 0 {
+                    break;
+                }
+                (v, t, player) = (start, t0, player - 1);
+                routes.push(Route::new(start));
+                continue;
+            }
+            let route = routes.last_mut().unwrap();
+            let flow = self.flow_map[v as usize];
+            if !opened.has(v) && flow > 0 {
+                let new_opened = opened.add(v);
+                if (t - 1) * flow + score(v, new_opened, t - 1, player, &mut states, &conditions)
+                    == here
+                {
+                    route.opened.push(v);
+                    (opened, t) = (new_opened, t - 1);
+                    continue;
+                }
+            }
+            let next = *self.conn_map[v as usize]
+                .iter()
+                .find(|n| score(**n, opened, t - 1, player, &mut states, &conditions) == here)
+                .expect("No move reproduces the best score!");
+            route.path.push(next);
+            (v, t) = (next, t - 1);
+        }
+
+        Solution {
+            score: best,
+            routes,
+        }
+    }
+
+    // Render the valve network as a Graphviz DOT graph. Valves are shaded
+    // and sized by flow rate, and the given routes are highlighted (one
+    // color per player), with opened valves drawn with a bold outline.
+    fn to_dot(&self, routes: &[Route]) -> String {
+        const ROUTE_COLORS: [&str; 4] = ["red", "blue", "darkgreen", "purple"];
+        let max_flow = self
+            .flow_map
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        let mut ret = String::new();
+        writeln!(ret, "graph valves {{").unwrap();
+        writeln!(ret, "  node [style=filled, colorscheme=ylorrd9];").unwrap();
+        for (v, name) in self.valve_names.iter().enumerate() {
+            let flow = self.flow_map[v];
+            let shade = 1 + flow * 8 / max_flow;
+            let size = 0.5 + f64::from(flow) / f64::from(max_flow);
+            let opener = routes
+                .iter()
+                .position(|route| route.opened.contains(&(v as u32)));
+            let outline = match opener {
+                Some(player) => format!(
+                    ", color={}, penwidth=4",
+                    ROUTE_COLORS[player % ROUTE_COLORS.len()]
+                ),
+                None => String::new(),
+            };
+            writeln!(
+                ret,
+                "  {name} [label=\"{name}\\n{flow}\", fillcolor={shade}, width={size:.2}, height={size:.2}{outline}];"
+            )
+            .unwrap();
+        }
+        let edges: HashSet<(u32, u32)> = self
+            .conn_map
+            .iter()
+            .enumerate()
+            .flat_map(|(a, conns)| conns.iter().map(move |&b| (a as u32, b)))
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        for (a, b) in edges.iter().sorted() {
+            let (a_name, b_name) = (
+                &self.valve_names[*a as usize],
+                &self.valve_names[*b as usize],
+            );
+            writeln!(ret, "  {a_name} -- {b_name};").unwrap();
+        }
+        for (player, route) in routes.iter().enumerate() {
+            let color = ROUTE_COLORS[player % ROUTE_COLORS.len()];
+            for (a, b) in route.path.iter().tuple_windows() {
+                let (a_name, b_name) = (
+                    &self.valve_names[*a as usize],
+                    &self.valve_names[*b as usize],
+                );
+                writeln!(
+                    ret,
+                    "  {a_name} -- {b_name} [color={color}, penwidth=3, constraint=false];"
+                )
+                .unwrap();
+            }
+        }
+        writeln!(ret, "}}").unwrap();
+        ret
     }
 }
 
@@ -210,6 +350,18 @@ fn main() {
     let world = World::construct(valves, 30, 1);
     let start = world.get("AA");
 
-    println!("Part 1: {}", world.run(start, OpenValves(0), 30, 0));
-    println!("Part 2: {}", world.run(start, OpenValves(0), 26, 1));
+    // Optionally write the valve network (with part 2's routes) to a DOT file
+    let args: Vec<String> = env::args().collect();
+    let dot_path = args
+        .iter()
+        .position(|arg| arg == "--dot")
+        .map(|i| args.get(i + 1).expect("Missing filename after --dot"));
+
+    println!("Part 1: {}", world.run(start, OpenValves(0), 30, 0).score);
+    let solution = world.run(start, OpenValves(0), 26, 1);
+    println!("Part 2: {}", solution.score);
+
+    if let Some(path) = dot_path {
+        fs::write(path, world.to_dot(&solution.routes)).expect("Failed to write DOT file");
+    }
 }
@@ -1,6 +1,14 @@
 use std::cmp::max;
-use std::io;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
 use std::ops::Add;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fs, io, thread};
+
+use anyhow::{anyhow, Error, Result};
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialOrd, PartialEq)]
 struct Pos {
@@ -37,123 +45,201 @@ impl Pos {
     }
 }
 
-const ROCKS: [[Pos; 5]; 5] = [
-    [
-        Pos { y: 0, x: 0 },
-        Pos { y: 0, x: 1 },
-        Pos { y: 0, x: 2 },
-        Pos { y: 0, x: 3 },
-        Pos { y: 0, x: 0 },
-    ],
-    [
-        Pos { y: 0, x: 1 },
-        Pos { y: 1, x: 0 },
-        Pos { y: 1, x: 1 },
-        Pos { y: 1, x: 2 },
-        Pos { y: 2, x: 1 },
-    ],
-    [
-        Pos { y: 0, x: 0 },
-        Pos { y: 0, x: 1 },
-        Pos { y: 0, x: 2 },
-        Pos { y: 1, x: 2 },
-        Pos { y: 2, x: 2 },
-    ],
-    [
-        Pos { y: 0, x: 0 },
-        Pos { y: 1, x: 0 },
-        Pos { y: 2, x: 0 },
-        Pos { y: 3, x: 0 },
-        Pos { y: 0, x: 0 },
-    ],
-    [
-        Pos { y: 0, x: 0 },
-        Pos { y: 0, x: 1 },
-        Pos { y: 1, x: 0 },
-        Pos { y: 1, x: 1 },
-        Pos { y: 0, x: 0 },
-    ],
-];
-
-#[derive(Clone, Copy, Debug)]
-struct Rock([Pos; 5]);
+// The rock shapes from the puzzle, in the same format as a --rocks file:
+// One block of '#' (rock) and '.' (air) per rock, separated by blank lines
+const DEFAULT_ROCKS: &str = "\
+####
+
+.#.
+###
+.#.
+
+..#
+..#
+###
+
+#
+#
+#
+#
+
+##
+##
+";
+
+// One row of the chamber or of a rock, with bit x set for rock at column x
+type Row = u16;
+
+// A rock shape as one bitmask per row, bottom row first, placed against the
+// left edge
+#[derive(Clone, Debug)]
+struct Rock {
+    rows: Vec<Row>,
+    width: i32,
+}
 
-impl Rock {
-    fn from(raw: [Pos; 5]) -> Self {
-        Self(raw)
+impl FromStr for Rock {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Rock cells, with the bottom line of the drawing at y = 0
+        let mut cells = Vec::new();
+        for (y, line) in s.lines().rev().enumerate() {
+            for (x, c) in line.trim().chars().enumerate() {
+                match c {
+                    '#' => cells.push(Pos::new(y as i32, x as i32)),
+                    '.' => {}
+                    _ => return Err(anyhow!("Unexpected {c:?} in rock shape {s:?}")),
+                }
+            }
+        }
+        if cells.is_empty() {
+            return Err(anyhow!("Rock shape {s:?} has no rock cells"));
+        }
+        // Drop empty rows and columns around the rock, so that it lands on
+        // its lowest cells and starts two units from the wall
+        let bottom = cells.iter().map(|pos| pos.y).min().unwrap();
+        let left = cells.iter().map(|pos| pos.x).min().unwrap();
+        let height = cells.iter().map(|pos| pos.y).max().unwrap() - bottom + 1;
+        let width = cells.iter().map(|pos| pos.x).max().unwrap() - left + 1;
+        if width > Row::BITS as i32 {
+            return Err(anyhow!("Rock shape {s:?} is too wide"));
+        }
+        let mut rows = vec![0; height as usize];
+        for pos in cells {
+            rows[(pos.y - bottom) as usize] |= 1 << (pos.x - left);
+        }
+        Ok(Self { rows, width })
+    }
+}
+
+impl fmt::Display for Rock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in (0..self.rows.len() as i32).rev() {
+            for x in 0..self.width {
+                let c = if self.contains(Pos::new(y, x)) {
+                    '#'
+                } else {
+                    '.'
+                };
+                write!(f, "{c}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
     }
+}
 
-    fn translate(&self, delta: Pos) -> Self {
-        Self([
-            self.0[0] + delta,
-            self.0[1] + delta,
-            self.0[2] + delta,
-            self.0[3] + delta,
-            self.0[4] + delta,
-        ])
+impl Rock {
+    // Rock shapes separated by blank lines
+    fn parse_all(s: &str) -> Result<Vec<Self>> {
+        let mut ret = Vec::new();
+        let mut block = String::new();
+        for line in s.lines().chain([""]) {
+            if !line.trim().is_empty() {
+                block.push_str(line);
+                block.push('\n');
+            } else if !block.is_empty() {
+                ret.push(block.parse()?);
+                block.clear();
+            }
+        }
+        Ok(ret)
     }
 
+    // Is 'pos' (relative to the rock's bottom left corner) part of this rock?
     fn contains(&self, pos: Pos) -> bool {
-        self.0.iter().any(|p| *p == pos)
+        0 <= pos.y
+            && pos.y < self.rows.len() as i32
+            && 0 <= pos.x
+            && pos.x < self.width
+            && self.rows[pos.y as usize] & (1 << pos.x) != 0
     }
 }
 
+#[derive(Clone)]
 struct Chamber {
-    rocks: Vec<[bool; 7]>,
-    falling: Option<Rock>,
+    width: i32,
+    rocks: Vec<Row>,
+    shapes: Vec<Rock>,
+    falling: Option<Pos>, // bottom left corner of the current shape
     jets: Vec<Pos>,
     num_turns: u64,
     num_landed: u64,
 }
 
 impl Chamber {
-    fn construct(jets: Vec<Pos>) -> Self {
+    fn construct(jets: Vec<Pos>, shapes: Vec<Rock>, width: i32) -> Result<Self> {
+        if shapes.is_empty() {
+            return Err(anyhow!("Need at least one rock shape"));
+        }
+        if !(0 < width && width <= Row::BITS as i32) {
+            return Err(anyhow!("Chamber width must be 1 to {}", Row::BITS));
+        }
+        // Rocks appear two units away from the left wall, and must fit
+        if let Some((i, rock)) = shapes
+            .iter()
+            .enumerate()
+            .find(|(_, rock)| 2 + rock.width > width)
+        {
+            return Err(anyhow!(
+                "Rock shape {} needs a chamber at least {} wide:\n{rock}",
+                i + 1,
+                2 + rock.width
+            ));
+        }
         let mut ret = Self {
+            width,
             rocks: Vec::new(),
+            shapes,
             falling: None,
             jets,
             num_turns: 0,
             num_landed: 0,
         };
         ret.next_rock();
-        ret
+        Ok(ret)
     }
 
     fn top(&self) -> i32 {
         self.rocks.len() as i32
     }
 
+    fn shape(&self) -> &Rock {
+        &self.shapes[self.num_landed as usize % self.shapes.len()]
+    }
+
     fn top_w_falling(&self) -> i32 {
         max(
             self.top(),
-            match &self.falling {
-                Some(rock) => rock.0.iter().map(|pos| pos.y + 1).max().unwrap_or_default(),
+            match self.falling {
+                Some(pos) => pos.y + self.shape().rows.len() as i32,
                 None => 0,
             },
         )
     }
 
-    fn is_rock(&self, pos: Pos) -> bool {
-        assert!(0 <= pos.x && pos.x < 7);
-        (pos.y as usize) < self.rocks.len() && self.rocks[pos.y as usize][pos.x as usize]
+    fn row(&self, y: i32) -> Row {
+        self.rocks.get(y as usize).copied().unwrap_or_default()
     }
 
-    fn add_rock(&mut self, pos: Pos) {
-        assert!(0 <= pos.x && pos.x < 7);
-        while (pos.y as usize) >= self.rocks.len() {
-            self.rocks
-                .push([false, false, false, false, false, false, false]);
-        }
-        self.rocks[pos.y as usize][pos.x as usize] = true;
+    fn is_rock(&self, pos: Pos) -> bool {
+        assert!(0 <= pos.x && pos.x < self.width);
+        self.row(pos.y) & (1 << pos.x) != 0
     }
 
     fn render(&self, lines: usize) -> String {
         let mut ret = String::new();
-        for y in ((self.top_w_falling() - (lines as i32))..=self.top_w_falling()).rev() {
+        let bottom = max(0, self.top_w_falling() - (lines as i32));
+        for y in (bottom..=self.top_w_falling()).rev() {
             ret.push('|');
-            for x in 0..7 {
+            for x in 0..self.width {
                 let pos = Pos::new(y, x);
-                if self.falling.map(|r| r.contains(pos)).unwrap_or_default() {
+                if self
+                    .falling
+                    .map(|at| self.shape().contains(Pos::new(y - at.y, x - at.x)))
+                    .unwrap_or_default()
+                {
                     ret.push('@');
                 } else if self.is_rock(pos) {
                     ret.push('#');
@@ -164,34 +250,45 @@ impl Chamber {
             ret.push_str("|\n");
         }
         ret.push('+');
-        for _ in 0..7 {
+        for _ in 0..self.width {
             ret.push('-');
         }
         ret.push_str("+\n");
         ret
     }
 
-    fn overlaps(&self, rock: &Rock) -> bool {
-        rock.0
-            .iter()
-            .any(|pos| pos.x < 0 || pos.x >= 7 || pos.y < 0 || self.is_rock(*pos))
+    // The top 'depth' rows, from the top down, with the floor as full rows
+    fn top_rows(&self, depth: i32) -> Vec<Row> {
+        (self.top() - depth..self.top())
+            .rev()
+            .map(|y| if y < 0 { Row::MAX } else { self.row(y) })
+            .collect()
     }
 
-    fn floats(&self, rock: &Rock) -> bool {
-        !self.overlaps(&rock.translate(Pos::down()))
+    fn overlaps(&self, rock: &Rock, pos: Pos) -> bool {
+        pos.x < 0
+            || pos.x + rock.width > self.width
+            || pos.y < 0
+            || rock
+                .rows
+                .iter()
+                .enumerate()
+                .any(|(dy, &row)| self.row(pos.y + dy as i32) & (row << pos.x) != 0)
+    }
+
+    fn floats(&self, rock: &Rock, pos: Pos) -> bool {
+        !self.overlaps(rock, pos + Pos::down())
     }
 
     fn next_rock(&mut self) {
         assert!(self.falling.is_none());
-        let start_pos = Pos::new(self.top() + 3, 2);
-        let rock = Rock::from(ROCKS[self.num_landed as usize % ROCKS.len()]);
-        self.falling = Some(rock.translate(start_pos));
+        self.falling = Some(Pos::new(self.top() + 3, 2));
     }
 
     fn move_rock(&mut self, delta: Pos) -> bool {
-        if let Some(rock) = &self.falling {
-            let moved = rock.translate(delta);
-            if !self.overlaps(&moved) {
+        if let Some(pos) = self.falling {
+            let moved = pos + delta;
+            if !self.overlaps(self.shape(), moved) {
                 self.falling = Some(moved);
                 true
             } else {
@@ -210,55 +307,190 @@ impl Chamber {
         self.move_rock(Pos::down())
     }
 
-    fn land(&mut self) {
-        if let Some(rock) = self.falling {
-            assert!(!self.overlaps(&rock) && !self.floats(&rock));
-            rock.0.iter().for_each(|pos| {
-                self.add_rock(*pos);
-            });
-            self.falling = None;
+    fn land(&mut self) -> Pos {
+        if let Some(pos) = self.falling.take() {
+            let rock = &self.shapes[self.num_landed as usize % self.shapes.len()];
+            debug_assert!(!self.overlaps(rock, pos) && !self.floats(rock, pos));
+            for (dy, &row) in rock.rows.iter().enumerate() {
+                let y = pos.y as usize + dy;
+                if y >= self.rocks.len() {
+                    self.rocks.resize(y + 1, 0);
+                }
+                self.rocks[y] |= row << pos.x;
+            }
             self.num_landed += 1;
+            pos
         } else {
             unreachable!("No falling rock to land!");
         }
     }
 
-    fn turn(&mut self) -> bool {
+    // Let the jet blow and the rock fall, calling 'show' after each of them.
+    // Returns where the rock landed, if it did.
+    fn turn_with(&mut self, show: &mut impl FnMut(&Self)) -> Option<Pos> {
         assert!(self.falling.is_some());
         let blow = self.jets[self.num_turns as usize % self.jets.len()];
         self.blow(blow);
         self.num_turns += 1;
-        if !self.fall() {
-            self.land();
+        show(self);
+        let landed = if !self.fall() {
+            let pos = self.land();
             self.next_rock();
-            true
+            Some(pos)
         } else {
-            false
+            None
+        };
+        show(self);
+        landed
+    }
+
+    fn turn(&mut self) -> Option<Pos> {
+        self.turn_with(&mut |_| {})
+    }
+
+    fn turn_until_land(&mut self) -> Pos {
+        loop {
+            if let Some(pos) = self.turn() {
+                return pos;
+            }
         }
     }
 
-    fn turn_until_land(&mut self) {
+    fn turn_until_land_with(&mut self, show: &mut impl FnMut(&Self)) -> Pos {
         loop {
-            if self.turn() {
-                return;
+            if let Some(pos) = self.turn_with(show) {
+                return pos;
             }
         }
     }
+}
 
-    fn land_n_rocks(&mut self, n: usize) {
-        for _ in 0..n {
-            self.turn_until_land();
+// Draws the chamber after every jet push and fall, either redrawn in place
+// in the terminal, or written as numbered text files to a directory
+struct Animation {
+    delay: Option<Duration>,
+    frame_dir: Option<PathBuf>,
+    lines: usize,
+    num_frames: usize,
+}
+
+impl Animation {
+    fn frame(&self, chamber: &Chamber) -> String {
+        // The jet that will push the falling rock next
+        let jet = chamber.num_turns as usize % chamber.jets.len();
+        let direction = if chamber.jets[jet] == Pos::left() {
+            '<'
+        } else {
+            '>'
+        };
+        format!(
+            "Rocks landed: {}, next jet: {jet} ({direction}), height: {}\n{}",
+            chamber.num_landed,
+            chamber.top(),
+            chamber.render(self.lines)
+        )
+    }
+
+    fn show(&mut self, chamber: &Chamber) {
+        let frame = self.frame(chamber);
+        if let Some(delay) = self.delay {
+            // Move cursor to top left and clear the screen below it
+            print!("\x1b[H\x1b[J{frame}");
+            io::stdout().flush().unwrap();
+            thread::sleep(delay);
+        }
+        if let Some(dir) = &self.frame_dir {
+            let path = dir.join(format!("{:06}.txt", self.num_frames));
+            fs::write(path, frame).expect("Failed to write frame");
         }
+        self.num_frames += 1;
     }
 }
 
-fn main() {
-    let mut line = String::new();
-    io::stdin()
-        .read_line(&mut line)
-        .expect("Failed to read line");
-    let jets: Vec<Pos> = line
-        .trim()
+// Tower heights after each landed rock, up to and including the first
+// repetition of the simulation state
+struct Tower {
+    heights: Vec<u64>,
+    cycle_start: usize,
+    period: usize,
+}
+
+impl Tower {
+    fn measure(chamber: &Chamber) -> Self {
+        (0..)
+            .find_map(|n| Self::measure_within(chamber.clone(), 16 << n))
+            .unwrap()
+    }
+
+    // Look for a repeat of the rock index, jet index and top 'depth' rows.
+    // This only proves a cycle if no rock in it reached any deeper than that,
+    // as only then does each rock in the next period see the same rows as its
+    // counterpart in this one. Otherwise, give up.
+    fn measure_within(mut chamber: Chamber, depth: i32) -> Option<Self> {
+        let mut heights = vec![0];
+        // How far below the top (when it appeared) each rock went, counting
+        // the row it landed on
+        let mut falls = Vec::new();
+        // Rock index, jet index and top rows -> number of rocks landed
+        let mut seen = HashMap::new();
+        loop {
+            let state = (
+                chamber.num_landed as usize % chamber.shapes.len(),
+                chamber.num_turns as usize % chamber.jets.len(),
+                chamber.top_rows(depth),
+            );
+            let landed = chamber.num_landed as usize;
+            if let Some(cycle_start) = seen.insert(state, landed) {
+                return falls[cycle_start..]
+                    .iter()
+                    .all(|&fall| fall <= depth)
+                    .then_some(Self {
+                        heights,
+                        cycle_start,
+                        period: landed - cycle_start,
+                    });
+            }
+            let top = chamber.top();
+            let pos = chamber.turn_until_land();
+            falls.push(top - (pos.y - 1));
+            heights.push(chamber.top() as u64);
+        }
+    }
+
+    fn height_per_period(&self) -> u64 {
+        self.heights[self.cycle_start + self.period] - self.heights[self.cycle_start]
+    }
+
+    fn height_after(&self, rocks: u64) -> u64 {
+        if let Some(&height) = self.heights.get(rocks as usize) {
+            return height;
+        }
+        let after_start = rocks - self.cycle_start as u64;
+        let num_periods = after_start / self.period as u64;
+        let remainder = (after_start % self.period as u64) as usize;
+        self.heights[self.cycle_start + remainder] + num_periods * self.height_per_period()
+    }
+
+    // How many rocks must land before the tower is at least 'height' tall?
+    fn rocks_until(&self, height: u64) -> u64 {
+        if height <= *self.heights.last().unwrap() {
+            return self.heights.partition_point(|&h| h < height) as u64;
+        }
+        // Skip whole periods, then find the first rock within the cycle
+        // that gets us there
+        let base = self.heights[self.cycle_start];
+        let per_period = self.height_per_period();
+        assert!(per_period > 0);
+        let num_periods = (height - base - 1) / per_period;
+        let needed = height - num_periods * per_period;
+        let cycle = &self.heights[self.cycle_start..=self.cycle_start + self.period];
+        let remainder = cycle.partition_point(|&h| h < needed);
+        (self.cycle_start + remainder) as u64 + num_periods * self.period as u64
+    }
+}
+
+fn parse_jets(line: &str) -> Vec<Pos> {
+    line.trim()
         .as_bytes()
         .iter()
         .map(|&b| match b {
@@ -266,50 +498,180 @@ fn main() {
             b'>' => Pos::right(),
             _ => unreachable!("Parse error: {b:?}"),
         })
-        .collect();
-
-    let mut chamber = Chamber::construct(jets.clone());
-    for _ in 0..2022 {
-        chamber.turn_until_land();
-    }
-    // println!("{}", chamber.render(15));
-    println!("Part 1: {}", chamber.top());
-
-    let mut chamber = Chamber::construct(jets.clone());
-    let total_rocks = 1_000_000_000_000u64;
-    // Find a number of rocks landed after which our top state repeats,
-    // record how many lines are in between
-    let proto_period = jets.len() * 5;
-    // println!("Proto_period is {}.", proto_period);
-    chamber.land_n_rocks(proto_period);
-    let lines_before_period = chamber.top();
-    let seen = chamber.render(15);
-    // println!("Looking for:\n{}", seen);
-    let mut iterations = 0;
-    loop {
-        chamber.turn_until_land();
-        iterations += 1;
-        if chamber.render(15) == seen {
-            // println!("{}: {}/{}\n{}", iterations, chamber.num_landed, chamber.top(), chamber.render(15));
-            break;
+        .collect()
+}
+
+// Parse a comma-separated list of numbers and ranges, e.g. "2022,0..100:10",
+// where ranges are exclusive (or inclusive with "..=") and the step defaults to 1
+fn parse_numbers(s: &str) -> Result<Vec<u64>> {
+    let mut ret = Vec::new();
+    for item in s.split(',').map(str::trim) {
+        let (range, step) = match item.split_once(':') {
+            Some((range, step)) => (range, step.parse()?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return Err(anyhow!("Step must be positive in {item:?}"));
+        }
+        if let Some((first, last)) = range.split_once("..=") {
+            ret.extend((first.parse::<u64>()?..=last.parse()?).step_by(step));
+        } else if let Some((first, end)) = range.split_once("..") {
+            ret.extend((first.parse::<u64>()?..end.parse()?).step_by(step));
+        } else {
+            ret.push(range.parse()?);
         }
     }
-    let period = iterations;
-    let lines_after_period = chamber.top();
-    let lines_per_period = chamber.top() - lines_before_period;
-    // println!("After {period} rocks, we have repeated our state with {lines_per_period} extra lines");
-    // Verify our period and #lines produced per period
-    chamber.land_n_rocks(period);
-    let lines_after_another_period = chamber.top();
-    assert!(lines_after_another_period - lines_after_period == lines_per_period);
-    // Now fast-forward to the end
-    let remainder = total_rocks - chamber.num_landed;
-    // println!("We need to drop {remainder} more rocks...");
-    let num_periods = remainder / (period as u64);
-    let remainder = remainder % (period as u64);
-    // println!("Simulate {num_periods} * {period} rocks, followed by dropping a remainder of {remainder} extra rocks");
-    chamber.land_n_rocks(remainder as usize);
-    assert!(chamber.num_landed + num_periods * period as u64 == total_rocks);
-    let total_lines = chamber.top() as u64 + lines_per_period as u64 * num_periods;
-    println!("Part 2: {}", total_lines);
+    Ok(ret)
+}
+
+fn main() {
+    // Optionally use other rock shapes (--rocks FILE) or chamber width (--width N),
+    // or cross-check against simulating N rocks without skipping cycles (--simulate N).
+    // Watch the first rocks fall (--animate, --fps N) or dump them as text frames
+    // (--frames DIR), for --animate-rocks N rocks (default 50) with --lines N lines.
+    // Ask for the height after each of a list of rock counts (--after LIST), or the
+    // number of rocks needed to reach each of a list of heights (--until-height LIST).
+    let mut shapes = DEFAULT_ROCKS.to_owned();
+    let mut width = 7;
+    let mut simulate: Option<u64> = None;
+    let mut animate = false;
+    let mut fps = 10.0;
+    let mut frame_dir: Option<PathBuf> = None;
+    let mut num_rocks: u64 = 50;
+    let mut lines = 20;
+    let mut after = None;
+    let mut until_height = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("Missing value after {arg}"))
+        };
+        match arg.as_str() {
+            "--rocks" => shapes = fs::read_to_string(value()).expect("Failed to read rocks file"),
+            "--width" => width = value().parse().unwrap(),
+            "--simulate" => simulate = Some(value().parse().unwrap()),
+            "--animate" => animate = true,
+            "--fps" => fps = value().parse().unwrap(),
+            "--frames" => frame_dir = Some(PathBuf::from(value())),
+            "--animate-rocks" => num_rocks = value().parse().unwrap(),
+            "--lines" => lines = value().parse().unwrap(),
+            "--after" => after = Some(parse_numbers(&value()).unwrap()),
+            "--until-height" => until_height = Some(parse_numbers(&value()).unwrap()),
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
+    let shapes = Rock::parse_all(&shapes).unwrap();
+
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .expect("Failed to read line");
+    let jets = parse_jets(&line);
+
+    if animate || frame_dir.is_some() {
+        let mut animation = Animation {
+            delay: animate.then(|| Duration::from_secs_f64(1.0 / fps)),
+            frame_dir,
+            lines,
+            num_frames: 0,
+        };
+        if let Some(dir) = &animation.frame_dir {
+            fs::create_dir_all(dir).expect("Failed to create frames directory");
+        }
+        let mut chamber = Chamber::construct(jets.clone(), shapes.clone(), width).unwrap();
+        animation.show(&chamber);
+        while chamber.num_landed < num_rocks {
+            chamber.turn_until_land_with(&mut |chamber| animation.show(chamber));
+        }
+    }
+
+    let chamber = Chamber::construct(jets.clone(), shapes.clone(), width).unwrap();
+    let tower = Tower::measure(&chamber);
+    println!("Part 1: {}", tower.height_after(2022));
+    println!("Part 2: {}", tower.height_after(1_000_000_000_000));
+
+    if let Some(list) = after {
+        for rocks in list {
+            println!("Height after {rocks} rocks: {}", tower.height_after(rocks));
+        }
+    }
+    if let Some(list) = until_height {
+        for height in list {
+            println!("Rocks until height {height}: {}", tower.rocks_until(height));
+        }
+    }
+
+    if let Some(n) = simulate {
+        let mut chamber = Chamber::construct(jets, shapes, width).unwrap();
+        for _ in 0..n {
+            chamber.turn_until_land();
+        }
+        println!("Simulated {n} rocks: {}", chamber.top());
+        assert_eq!(chamber.top() as u64, tower.height_after(n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chamber(jets: &str, width: i32) -> Chamber {
+        let shapes = Rock::parse_all(DEFAULT_ROCKS).unwrap();
+        Chamber::construct(parse_jets(jets), shapes, width).unwrap()
+    }
+
+    // Compare the tower's heights with plain simulation
+    fn check_against_simulation(chamber: &Chamber, num_rocks: u64) {
+        let tower = Tower::measure(chamber);
+        let mut chamber = chamber.clone();
+        for n in 1..=num_rocks {
+            chamber.turn_until_land();
+            assert_eq!(
+                chamber.top() as u64,
+                tower.height_after(n),
+                "After {n} rocks"
+            );
+        }
+    }
+
+    #[test]
+    fn example() {
+        let chamber = chamber(">>><<><>><<<>><>>><<<>>><<<><<<>><>><<>>", 7);
+        let tower = Tower::measure(&chamber);
+        assert_eq!(tower.height_after(2022), 3068);
+        assert_eq!(tower.height_after(1_000_000_000_000), 1514285714288);
+        check_against_simulation(&chamber, 5000);
+    }
+
+    // Columns that never get any rock used to keep the cycle from being found
+    #[test]
+    fn single_direction_jets() {
+        let tower = Tower::measure(&chamber("<", 7));
+        assert_eq!(tower.height_after(2022), 4448);
+        assert_eq!(tower.height_after(1_000_000_000_000), 2200000000000);
+        check_against_simulation(&chamber("<", 7), 5000);
+        check_against_simulation(&chamber(">", 7), 5000);
+        check_against_simulation(&chamber(">>><<<", 9), 5000);
+    }
+
+    #[test]
+    fn rock_shapes() {
+        // Empty rows and columns around a shape are dropped
+        let padded: Rock = "....\n.##.\n.#..\n....\n".parse().unwrap();
+        let plain: Rock = "##\n#.\n".parse().unwrap();
+        assert_eq!((&padded.rows, padded.width), (&plain.rows, plain.width));
+        assert_eq!(plain.to_string(), "##\n#.\n");
+
+        let crlf = Rock::parse_all(&DEFAULT_ROCKS.replace('\n', "\r\n")).unwrap();
+        let lf = Rock::parse_all(DEFAULT_ROCKS).unwrap();
+        assert_eq!(crlf.len(), 5);
+        for (a, b) in crlf.iter().zip(&lf) {
+            assert_eq!((&a.rows, a.width), (&b.rows, b.width));
+        }
+
+        assert!("...\n".parse::<Rock>().is_err());
+        assert!("#x#\n".parse::<Rock>().is_err());
+        assert!(Chamber::construct(parse_jets("<"), lf, 5).is_err());
+    }
 }
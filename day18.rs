@@ -1,11 +1,12 @@
-use std::cmp::{max, min};
-use std::collections::BTreeSet;
-use std::collections::HashSet;
-use std::io;
+use std::cmp::{max, min, Reverse};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
 use std::ops::Add;
 use std::str::FromStr;
+use std::{env, fs, io};
 
 use anyhow::{anyhow, Error, Result};
+use itertools::Itertools;
 
 type Coord = i32;
 
@@ -46,20 +47,51 @@ impl FromStr for Pos {
     }
 }
 
+const DIRECTIONS: [Pos; 6] = [
+    Pos { x: -1, y: 0, z: 0 }, // left
+    Pos { x: 1, y: 0, z: 0 },  // right
+    Pos { x: 0, y: -1, z: 0 }, // below
+    Pos { x: 0, y: 1, z: 0 },  // above
+    Pos { x: 0, y: 0, z: -1 }, // behind
+    Pos { x: 0, y: 0, z: 1 },  // infront
+];
+
 impl Pos {
     fn new(x: Coord, y: Coord, z: Coord) -> Self {
         Self { x, y, z }
     }
 
     fn adjacents(self) -> [Self; 6] {
-        [
-            self + Pos::new(-1, 0, 0), // left
-            self + Pos::new(1, 0, 0),  // right
-            self + Pos::new(0, -1, 0), // below
-            self + Pos::new(0, 1, 0),  // above
-            self + Pos::new(0, 0, -1), // behind
-            self + Pos::new(0, 0, 1),  // infront
-        ]
+        DIRECTIONS.map(|d| self + d)
+    }
+
+    // Coordinates along 'axis' (0 = x, 1 = y, 2 = z) and then the two other
+    // axes, ordered so that the plane they span has 'axis' as its normal
+    fn rotated(self, axis: usize) -> (Coord, Coord, Coord) {
+        match axis {
+            0 => (self.x, self.y, self.z),
+            1 => (self.y, self.z, self.x),
+            2 => (self.z, self.x, self.y),
+            _ => unreachable!("No axis {axis}"),
+        }
+    }
+
+    fn unrotated(axis: usize, (w, u, v): (Coord, Coord, Coord)) -> Self {
+        match axis {
+            0 => Self::new(w, u, v),
+            1 => Self::new(v, w, u),
+            2 => Self::new(u, v, w),
+            _ => unreachable!("No axis {axis}"),
+        }
+    }
+
+    fn axis(self) -> usize {
+        match self {
+            Pos { x: _, y: 0, z: 0 } => 0,
+            Pos { x: 0, y: _, z: 0 } => 1,
+            Pos { x: 0, y: 0, z: _ } => 2,
+            _ => unreachable!("{self:?} is not along an axis"),
+        }
     }
 }
 
@@ -91,8 +123,39 @@ impl BBox {
         }
     }
 
-    fn _volume(&self) -> u32 {
-        ((self.max.x - self.min.x) * (self.max.y - self.min.y) * (self.max.z - self.min.z)) as u32
+    // The number of positions along each axis
+    fn size(&self) -> (usize, usize, usize) {
+        (
+            (self.max.x - self.min.x) as usize,
+            (self.max.y - self.min.y) as usize,
+            (self.max.z - self.min.z) as usize,
+        )
+    }
+
+    fn volume(&self) -> usize {
+        let (x, y, z) = self.size();
+        x * y * z
+    }
+
+    fn padded(&self, margin: Coord) -> Self {
+        Self {
+            min: self.min + Pos::new(-margin, -margin, -margin),
+            max: self.max + Pos::new(margin, margin, margin),
+        }
+    }
+
+    fn positions(&self) -> impl Iterator<Item = Pos> {
+        let Self { min, max } = *self;
+        (min.x..max.x).flat_map(move |x| {
+            (min.y..max.y).flat_map(move |y| (min.z..max.z).map(move |z| Pos::new(x, y, z)))
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "x={}..{}, y={}..{}, z={}..{}",
+            self.min.x, self.max.x, self.min.y, self.max.y, self.min.z, self.max.z
+        )
     }
 
     fn contains(&self, p: &Pos) -> bool {
@@ -105,32 +168,443 @@ impl BBox {
     }
 }
 
-fn spread(bbox: &BBox, start: Pos, barriers: &HashSet<Pos>) -> HashSet<Pos> {
-    let mut active = BTreeSet::from([start]); // HashSet has no (efficient) .pop()
-    let mut settled = HashSet::new();
-    while let Some(cur) = active.iter().next().copied() {
-        active.remove(&cur); // BTreeSet.pop_first() is still experimental, *grumble*
-        settled.insert(cur);
+// A dense set of positions within a bounding box, one bit per position
+#[derive(Clone, Debug)]
+struct Voxels {
+    bbox: BBox,
+    bits: Vec<u64>,
+}
+
+impl Voxels {
+    fn new(bbox: BBox) -> Self {
+        Self {
+            bbox,
+            bits: vec![0; (bbox.volume() + 63) / 64],
+        }
+    }
+
+    // All of 'points', in a grid with 'margin' empty positions on each side
+    fn from_points(points: &[Pos], margin: Coord) -> Self {
+        let bbox = BBox::from(points.iter().copied()).unwrap().padded(margin);
+        let mut ret = Self::new(bbox);
+        points.iter().for_each(|p| {
+            ret.insert(*p);
+        });
+        ret
+    }
+
+    fn index(&self, p: &Pos) -> Option<usize> {
+        if !self.bbox.contains(p) {
+            return None;
+        }
+        let min = self.bbox.min;
+        let (x, y, z) = (p.x - min.x, p.y - min.y, p.z - min.z);
+        let (_, size_y, size_z) = self.bbox.size();
+        Some((x as usize * size_y + y as usize) * size_z + z as usize)
+    }
+
+    fn contains(&self, p: &Pos) -> bool {
+        self.index(p)
+            .map(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+            .unwrap_or_default()
+    }
+
+    // Add 'p', returning whether it was newly added
+    fn insert(&mut self, p: Pos) -> bool {
+        let i = self.index(&p).expect("Position outside voxel grid");
+        let was_set = self.bits[i / 64] & (1 << (i % 64)) != 0;
+        self.bits[i / 64] |= 1 << (i % 64);
+        !was_set
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Pos> + '_ {
+        self.bbox.positions().filter(|p| self.contains(p))
+    }
+}
+
+fn spread(bbox: &BBox, start: Pos, barriers: &Voxels) -> Voxels {
+    let mut settled = Voxels::new(*bbox);
+    let mut queue = VecDeque::from([start]);
+    settled.insert(start);
+    while let Some(cur) = queue.pop_front() {
         for next in cur.adjacents() {
-            if bbox.contains(&next) && !settled.contains(&next) && !barriers.contains(&next) {
-                active.insert(next);
+            if bbox.contains(&next) && !barriers.contains(&next) && settled.insert(next) {
+                queue.push_back(next);
             }
         }
     }
     settled
 }
 
+// Split 'cells' into groups of face-connected cells, largest group first
+fn components(cells: &Voxels) -> Vec<Vec<Pos>> {
+    let mut seen = Voxels::new(cells.bbox);
+    let mut ret = Vec::new();
+    for start in cells.iter() {
+        if !seen.insert(start) {
+            continue;
+        }
+        let mut todo = vec![start];
+        let mut component = Vec::new();
+        while let Some(cur) = todo.pop() {
+            component.push(cur);
+            for next in cur.adjacents() {
+                if cells.contains(&next) && seen.insert(next) {
+                    todo.push(next);
+                }
+            }
+        }
+        ret.push(component);
+    }
+    ret.sort_by_key(|component| Reverse(component.len()));
+    ret
+}
+
+// Print every enclosed air pocket (the air that steam cannot reach), and
+// every separate droplet (group of connected cubes)
+fn report_components(cubes: &Voxels, steam: &Voxels) {
+    let faces_towards = |component: &[Pos], others: &Voxels| {
+        component
+            .iter()
+            .flat_map(|p| p.adjacents())
+            .filter(|p| others.contains(p))
+            .count()
+    };
+    let mut air = Voxels::new(cubes.bbox);
+    for p in cubes.bbox.positions() {
+        if !cubes.contains(&p) && !steam.contains(&p) {
+            air.insert(p);
+        }
+    }
+    for (i, pocket) in components(&air).iter().enumerate() {
+        println!(
+            "Air pocket {}: volume {}, bbox {}, hidden surface {}",
+            i + 1,
+            pocket.len(),
+            BBox::from(pocket.iter().copied()).unwrap().describe(),
+            faces_towards(pocket, cubes),
+        );
+    }
+    for (i, droplet) in components(cubes).iter().enumerate() {
+        println!(
+            "Droplet {}: {} cubes, bbox {}, surface {} (exterior {})",
+            i + 1,
+            droplet.len(),
+            BBox::from(droplet.iter().copied()).unwrap().describe(),
+            droplet
+                .iter()
+                .flat_map(|p| p.adjacents())
+                .filter(|p| !cubes.contains(p))
+                .count(),
+            faces_towards(droplet, steam),
+        );
+    }
+}
+
+// A rectangle on the surface, with corners in counter-clockwise order when
+// seen from the outside
+#[derive(Debug)]
+struct Quad {
+    normal: Pos,
+    corners: [Pos; 4],
+}
+
+impl Quad {
+    // The rectangle [u0, u1) x [v0, v1) in the plane at 'w' along the normal
+    fn from_rect(
+        normal: Pos,
+        w: Coord,
+        (u0, v0): (Coord, Coord),
+        (u1, v1): (Coord, Coord),
+    ) -> Self {
+        let axis = normal.axis();
+        let mut corners = [(u0, v0), (u1, v0), (u1, v1), (u0, v1)];
+        if normal.rotated(axis).0 < 0 {
+            corners.reverse();
+        }
+        Self {
+            normal,
+            corners: corners.map(|(u, v)| Pos::unrotated(axis, (w, u, v))),
+        }
+    }
+}
+
+// Greedily merge unit squares into rectangles: grow each rectangle along v
+// first, and then along u for as long as the whole column is present
+fn merge_squares(squares: &HashSet<(Coord, Coord)>) -> Vec<((Coord, Coord), (Coord, Coord))> {
+    let mut done = HashSet::new();
+    let mut ret = Vec::new();
+    let available = |done: &HashSet<_>, sq| squares.contains(&sq) && !done.contains(&sq);
+    for &(u0, v0) in squares.iter().sorted() {
+        if done.contains(&(u0, v0)) {
+            continue;
+        }
+        let v1 = (v0..).find(|&v| !available(&done, (u0, v))).unwrap();
+        let u1 = (u0 + 1..)
+            .find(|&u| !(v0..v1).all(|v| available(&done, (u, v))))
+            .unwrap();
+        done.extend((u0..u1).flat_map(|u| (v0..v1).map(move |v| (u, v))));
+        ret.push(((u0, v0), (u1, v1)));
+    }
+    ret
+}
+
+// The faces of 'cubes' that face 'steam', optionally with coplanar faces
+// merged into larger rectangles
+fn exterior_quads(cubes: &Voxels, steam: &Voxels, merge: bool) -> Vec<Quad> {
+    // Group unit faces by direction and plane
+    let mut planes: HashMap<(usize, Coord), HashSet<(Coord, Coord)>> = HashMap::new();
+    for cube in cubes.iter() {
+        for (d, normal) in DIRECTIONS.iter().enumerate() {
+            if steam.contains(&(cube + *normal)) {
+                let axis = normal.axis();
+                let (w, u, v) = cube.rotated(axis);
+                let w = if normal.rotated(axis).0 > 0 { w + 1 } else { w };
+                planes.entry((d, w)).or_default().insert((u, v));
+            }
+        }
+    }
+    let mut ret = Vec::new();
+    for ((d, w), squares) in planes.iter().sorted_by_key(|(key, _)| *key) {
+        let rects = if merge {
+            merge_squares(squares)
+        } else {
+            squares
+                .iter()
+                .sorted()
+                .map(|&(u, v)| ((u, v), (u + 1, v + 1)))
+                .collect()
+        };
+        ret.extend(
+            rects
+                .into_iter()
+                .map(|(start, end)| Quad::from_rect(DIRECTIONS[*d], *w, start, end)),
+        );
+    }
+    ret
+}
+
+fn to_stl(quads: &[Quad]) -> String {
+    let mut ret = String::new();
+    writeln!(ret, "solid droplet").unwrap();
+    for Quad { normal, corners } in quads {
+        for triangle in [[0, 1, 2], [0, 2, 3]] {
+            writeln!(ret, "facet normal {} {} {}", normal.x, normal.y, normal.z).unwrap();
+            writeln!(ret, "  outer loop").unwrap();
+            for Pos { x, y, z } in triangle.map(|i| corners[i]) {
+                writeln!(ret, "    vertex {x} {y} {z}").unwrap();
+            }
+            writeln!(ret, "  endloop").unwrap();
+            writeln!(ret, "endfacet").unwrap();
+        }
+    }
+    writeln!(ret, "endsolid droplet").unwrap();
+    ret
+}
+
+fn to_obj(quads: &[Quad]) -> String {
+    let mut vertices = HashMap::new();
+    let mut ret = String::new();
+    let mut faces = String::new();
+    for Quad { normal: _, corners } in quads {
+        write!(faces, "f").unwrap();
+        for corner in corners {
+            let next = vertices.len() + 1; // OBJ indices are 1-based
+            let i = *vertices.entry(*corner).or_insert_with(|| {
+                writeln!(ret, "v {} {} {}", corner.x, corner.y, corner.z).unwrap();
+                next
+            });
+            write!(faces, " {i}").unwrap();
+        }
+        writeln!(faces).unwrap();
+    }
+    ret + &faces
+}
+
+// Read the voxels of all models in a MagicaVoxel .vox file. The file is a
+// "VOX " header and version, followed by a MAIN chunk whose children include
+// one XYZI chunk (with x, y, z and color bytes per voxel) per model.
+fn load_vox(bytes: &[u8]) -> Result<Vec<Pos>> {
+    let read_u32 = |at: usize| -> Result<usize> {
+        let raw = bytes
+            .get(at..at + 4)
+            .ok_or_else(|| anyhow!("Truncated .vox file at byte {at}"))?;
+        Ok(u32::from_le_bytes(raw.try_into()?) as usize)
+    };
+    if bytes.get(0..4) != Some(b"VOX ") || bytes.get(8..12) != Some(b"MAIN") {
+        return Err(anyhow!("Not a MagicaVoxel .vox file"));
+    }
+    let children_start = 20 + read_u32(12)?;
+    let children_end = children_start + read_u32(16)?;
+    let mut ret = Vec::new();
+    let mut at = children_start;
+    while at < children_end {
+        let id = bytes
+            .get(at..at + 4)
+            .ok_or_else(|| anyhow!("Truncated .vox file at byte {at}"))?;
+        let content_size = read_u32(at + 4)?;
+        let content = at + 12;
+        if id == b"XYZI" {
+            let num_voxels = read_u32(content)?;
+            let voxels = bytes
+                .get(content + 4..content + 4 + 4 * num_voxels)
+                .ok_or_else(|| anyhow!("Truncated XYZI chunk at byte {at}"))?;
+            ret.extend(
+                voxels
+                    .chunks(4)
+                    .map(|v| Pos::new(v[0] as Coord, v[1] as Coord, v[2] as Coord)),
+            );
+        }
+        at = content + content_size + read_u32(at + 8)?;
+    }
+    Ok(ret)
+}
+
+// Read a stack of ASCII slices, one per z, separated by blank lines. Each line
+// in a slice is a row (y), and each '#' in a row is a cube (x).
+fn parse_slices(s: &str) -> Result<Vec<Pos>> {
+    let mut ret = Vec::new();
+    for (z, slice) in s.split("\n\n").enumerate() {
+        for (y, line) in slice.lines().enumerate() {
+            for (x, c) in line.trim().chars().enumerate() {
+                match c {
+                    '#' => ret.push(Pos::new(x as Coord, y as Coord, z as Coord)),
+                    '.' => {}
+                    _ => return Err(anyhow!("Unexpected {c:?} in slice {z}, row {y}")),
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+// Fill the inside of the closed mesh in an ASCII STL file with cubes of size
+// 'resolution', by casting a ray along z through the center of each (x, y)
+// column and filling between alternate crossings of the mesh
+fn voxelise_stl(s: &str, resolution: f64) -> Result<Vec<Pos>> {
+    let mut vertices = Vec::new();
+    for line in s.lines().map(str::trim) {
+        if let Some(coords) = line.strip_prefix("vertex") {
+            let coords: Vec<f64> = coords
+                .split_whitespace()
+                .map(|c| c.parse::<f64>().map(|c| c / resolution))
+                .collect::<Result<_, _>>()?;
+            match coords[..] {
+                [x, y, z] => vertices.push((x, y, z)),
+                _ => return Err(anyhow!("Failed to parse vertex from {line:?}")),
+            }
+        }
+    }
+    if vertices.len() % 3 != 0 {
+        return Err(anyhow!("STL vertices do not make up whole triangles"));
+    }
+
+    // Nudge the rays off the grid lines and their diagonals (by different
+    // amounts in x and y), so they don't pass exactly through triangle edges
+    let center = |i: Coord, j: Coord| (i as f64 + 0.5 + 3e-6, j as f64 + 0.5 + 1e-6);
+    let mut crossings: HashMap<(Coord, Coord), Vec<f64>> = HashMap::new();
+    for (a, b, c) in vertices.into_iter().tuples() {
+        let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+        if area == 0.0 {
+            continue; // parallel to the rays
+        }
+        let min_x = a.0.min(b.0).min(c.0).floor() as Coord;
+        let max_x = a.0.max(b.0).max(c.0).ceil() as Coord;
+        let min_y = a.1.min(b.1).min(c.1).floor() as Coord;
+        let max_y = a.1.max(b.1).max(c.1).ceil() as Coord;
+        for (i, j) in (min_x..max_x).cartesian_product(min_y..max_y) {
+            let (x, y) = center(i, j);
+            // Barycentric weights of (x, y) in the triangle's xy projection
+            let wa = ((b.0 - x) * (c.1 - y) - (c.0 - x) * (b.1 - y)) / area;
+            let wb = ((c.0 - x) * (a.1 - y) - (a.0 - x) * (c.1 - y)) / area;
+            let wc = 1.0 - wa - wb;
+            if wa >= 0.0 && wb >= 0.0 && wc >= 0.0 {
+                let z = wa * a.2 + wb * b.2 + wc * c.2;
+                crossings.entry((i, j)).or_default().push(z);
+            }
+        }
+    }
+
+    let mut ret = Vec::new();
+    for ((i, j), mut zs) in crossings {
+        zs.sort_by(f64::total_cmp);
+        for (enter, exit) in zs.into_iter().tuples() {
+            let first = (enter - 0.5).ceil() as Coord;
+            let last = (exit - 0.5).floor() as Coord;
+            ret.extend((first..=last).map(|k| Pos::new(i, j, k)));
+        }
+    }
+    Ok(ret)
+}
+
+// Where to read the cubes from
+enum Source {
+    Stdin, // x,y,z lines
+    Vox(String),
+    Slices(String),
+    Stl(String),
+}
+
+impl Source {
+    fn load(&self, resolution: f64) -> Result<Vec<Pos>> {
+        let read = |path: &str| fs::read(path).map_err(|e| anyhow!("Failed to read {path}: {e}"));
+        let read_text = |path: &str| -> Result<String> { Ok(String::from_utf8(read(path)?)?) };
+        Ok(match self {
+            Source::Stdin => io::stdin()
+                .lines()
+                .map(|line| line?.parse())
+                .collect::<Result<_>>()?,
+            Source::Vox(path) => load_vox(&read(path)?)?,
+            Source::Slices(path) => parse_slices(&read_text(path)?)?,
+            Source::Stl(path) => voxelise_stl(&read_text(path)?, resolution)?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Mesh {
+    stl_path: Option<String>,
+    obj_path: Option<String>,
+    merge: bool,
+}
+
 fn main() {
-    let cubes: HashSet<Pos> = io::stdin()
-        .lines()
-        .map(Result::unwrap)
-        .map(|s| s.parse())
-        .map(Result::unwrap)
-        .collect();
+    // Optionally write the exterior surface as a mesh (--stl FILE, --obj FILE),
+    // with coplanar faces merged into larger rectangles (--merge). List enclosed
+    // air pockets and separate droplets with --pockets.
+    // Instead of x,y,z lines on stdin, read cubes from a MagicaVoxel file
+    // (--vox FILE), ASCII slices (--slices FILE) or by filling the inside of
+    // an ASCII STL mesh (--from-stl FILE) with cubes of size --resolution R.
+    let mut source = Source::Stdin;
+    let mut resolution = 1.0;
+    let mut mesh = Mesh::default();
+    let mut pockets = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("Missing value after {arg}"))
+        };
+        match arg.as_str() {
+            "--vox" => source = Source::Vox(value()),
+            "--slices" => source = Source::Slices(value()),
+            "--from-stl" => source = Source::Stl(value()),
+            "--resolution" => resolution = value().parse().unwrap(),
+            "--stl" => mesh.stl_path = Some(value()),
+            "--obj" => mesh.obj_path = Some(value()),
+            "--merge" => mesh.merge = true,
+            "--pockets" => pockets = true,
+            _ => panic!("Unknown option {arg:?}"),
+        }
+    }
+
+    let points = source.load(resolution).unwrap();
+    // Leave room for the steam to flow around the droplet
+    let cubes = Voxels::from_points(&points, 1);
 
     let adjacents: Vec<Pos> = cubes
         .iter()
-        .flat_map(|&p| p.adjacents().into_iter())
+        .flat_map(|p| p.adjacents().into_iter())
         .collect();
     let exposed_surface: Vec<Pos> = adjacents
         .iter()
@@ -139,10 +613,45 @@ fn main() {
         .collect();
     println!("Part 1: {}", exposed_surface.len());
 
-    let bbox = BBox::from(exposed_surface.clone().into_iter()).unwrap();
+    let bbox = cubes.bbox;
     let steam = spread(&bbox, bbox.min, &cubes);
     println!(
         "Part 2: {}",
         exposed_surface.iter().filter(|p| steam.contains(p)).count()
     );
+
+    if pockets {
+        report_components(&cubes, &steam);
+    }
+
+    if mesh.stl_path.is_some() || mesh.obj_path.is_some() {
+        let quads = exterior_quads(&cubes, &steam, mesh.merge);
+        if let Some(path) = mesh.stl_path {
+            fs::write(path, to_stl(&quads)).expect("Failed to write STL file");
+        }
+        if let Some(path) = mesh.obj_path {
+            fs::write(path, to_obj(&quads)).expect("Failed to write OBJ file");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Grids this big have more positions than fit in an i32
+    #[test]
+    fn large_grid() {
+        let bbox = BBox {
+            min: Pos::new(-700, -700, -700),
+            max: Pos::new(700, 700, 700),
+        };
+        assert_eq!(bbox.volume(), 1400 * 1400 * 1400);
+        let mut voxels = Voxels::new(bbox);
+        let corner = Pos::new(699, 699, 699);
+        assert!(voxels.insert(corner));
+        assert!(voxels.contains(&corner));
+        assert!(!voxels.contains(&Pos::new(699, 699, 698)));
+        assert_eq!(voxels.index(&corner), Some(bbox.volume() - 1));
+    }
 }
@@ -153,7 +153,7 @@ fn run_simulation(
 
     let state = (time, inventory.state(), robots.state());
     if seen.contains_key(&state) {
-        return seen.get(&state).unwrap().clone();
+        return *seen.get(&state).unwrap();
     }
 
     // What are we building?